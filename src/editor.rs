@@ -0,0 +1,3943 @@
+//! The `Editor` and its buffers, and everything that edits them.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::encoding::{decode_line, latin1, open_reader, starts_as_utf8, Encoding};
+use crate::input::{
+    printable_char, read_key, ARROW_DOWN, ARROW_LEFT, ARROW_RIGHT, ARROW_UP, BACKSPACE, CTRL_A,
+    CTRL_ARROW_LEFT, CTRL_ARROW_RIGHT, CTRL_C, CTRL_END, CTRL_HOME, CTRL_PAGE_DOWN, CTRL_PAGE_UP,
+    CTRL_R, CTRL_W, END, ENTER, ESCAPE, HOME, IDLE, MOUSE_CLICK, MOUSE_WHEEL_DOWN, MOUSE_WHEEL_UP,
+    PAGE_DOWN, PAGE_UP, PASTE_END, PASTE_START, TAB, WINDOW_RESIZED,
+};
+use crate::keymap::{default_keymap, help_lines, parse_action, parse_key, Action, Keymap};
+use crate::lines::{Lines, Source};
+use crate::render::{cursor_x_to_render_x, refresh_message_bar, refresh_screen};
+use crate::search::Search;
+use crate::syntax::{
+    detect_syntax, highlight_all, line_comment, update_highlight, HighlightOpen, Syntax,
+};
+use crate::terminal::{attach_to_terminal, update_window_size, write, InputBuffer, RawMode};
+use crate::theme::{Color, ColorDepth, Theme};
+
+pub const VERSION: &str = "0.0.1";
+const DEFAULT_TAB_STOP: usize = 8;
+const QUIT_TIMES: usize = 3;
+const DEFAULT_UNDO_LIMIT: usize = 1000;
+const DEFAULT_FILL_COLUMN: usize = 79;
+/// How many files' cursor positions are remembered.
+const MAX_SAVED_POSITIONS: usize = 1000;
+/// How many recently opened files are remembered.
+const MAX_RECENT_FILES: usize = 20;
+/// How many lines a turn of the mouse wheel scrolls by.
+const WHEEL_SCROLL_LINES: usize = 3;
+/// How long an operation goes before its progress is shown, and how often it's updated.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+const OPEN_PROMPT: &str = "Open: {} (ESC to cancel, Tab to complete)";
+/// How many lines past the bottom of the screen are read ahead of the user.
+pub const LOAD_AHEAD_LINES: usize = 1000;
+/// How many lines either side of the screen and the cursor stay in memory once read. Lines
+/// further off that weren't changed are read from the file again when they're needed.
+const KEPT_LINES: usize = 10_000;
+/// The commands files are formatted with, by extension, until the config says otherwise.
+/// They read the text on stdin and write it formatted to stdout.
+const DEFAULT_FORMATTERS: &[(&str, &str)] = &[
+    ("rs", "rustfmt"),
+    ("go", "gofmt"),
+    ("c", "clang-format"),
+    ("h", "clang-format"),
+    ("cpp", "clang-format"),
+];
+
+pub struct Editor {
+    pub columns: usize,
+    pub rows: usize,
+    pub raw_mode: Option<RawMode>,
+    pub input: InputBuffer,
+    pub buffers: Vec<Buffer>,
+    pub active_buffer: usize,
+    pub tab_stop: usize,
+    expand_tab: bool,
+    /// How wide a paragraph is rewrapped to.
+    fill_column: usize,
+    pub show_line_numbers: bool,
+    /// Whether spaces and tabs are drawn as symbols, to tell them apart.
+    pub show_whitespace: bool,
+    /// Whether long lines are wrapped onto the following screen rows instead of scrolling
+    /// sideways.
+    pub wrap: bool,
+    /// Whether typing replaces the character under the cursor instead of pushing it along.
+    pub overwrite: bool,
+    /// Whether the editor starts in normal mode and Escape goes back to it, like in vi.
+    modal: bool,
+    pub mode: Mode,
+    /// Whether the cursor is a block in normal and overwrite mode and a bar when inserting,
+    /// instead of whatever shape the terminal gives it.
+    pub cursor_shape: bool,
+    /// The first key of a two key command in normal mode, like the first d of dd.
+    pending: Option<char>,
+    ensure_final_newline: bool,
+    trim_trailing_whitespace: bool,
+    /// Whether duplicating lines moves the cursor onto the copy instead of leaving it on the
+    /// original.
+    duplicate_to_copy: bool,
+    /// Whether a file's contents from before it's first saved are kept next to it, with a `~`
+    /// after its name.
+    make_backup: bool,
+    /// The command each kind of file is formatted with, by extension.
+    formatters: HashMap<String, String>,
+    /// Whether saving creates the directories a file is saved in, instead of asking first.
+    mkdir_on_save: bool,
+    /// Whether files are opened read-only, as asked for with `-R`.
+    read_only: bool,
+    pub status_message: String,
+    pub status_message_time: Instant,
+    /// Where the screen is drawn, which is stdout unless something else wants to see it.
+    pub output: Box<dyn Write>,
+    /// What each row of the screen was last drawn as, so a refresh only writes the rows that
+    /// changed. Empty when the whole screen has to be drawn again.
+    pub drawn_rows: Vec<String>,
+    /// When `show_progress` last drew anything.
+    progress_time: Instant,
+    quit_times: usize,
+    /// Whether the last key was Ctrl-C, so pressing it again quits.
+    cancel_pressed: bool,
+    undo_limit: usize,
+    /// Between the markers of a bracketed paste, where keys are text and not commands.
+    pasting: bool,
+    /// Whether the last key pasted was a carriage return, whose line feed in a CRLF doesn't
+    /// break the line again.
+    pasted_cr: bool,
+    /// The text last cut with Ctrl-K, put back with Ctrl-U.
+    register: String,
+    /// Where stdout went before the terminal took its place, when text was piped in.
+    piped_output: Option<File>,
+    /// Whether the help screen is drawn over the text, until the next key.
+    pub show_help: bool,
+    /// The recently opened files to pick from, while they're shown over the text.
+    pub recent_files: Option<RecentFiles>,
+    pub keymap: Keymap,
+    pub theme: Theme,
+    /// How many colors the terminal can show, which the theme's colors are brought down to.
+    pub color_depth: ColorDepth,
+    /// The last search, whose options are kept for the next one.
+    pub search: Search,
+    /// Whether the search prompt is open, so the matches on screen are highlighted.
+    pub searching: bool,
+}
+
+/// A file open in the editor, along with where the user is in it.
+#[derive(Default)]
+pub struct Buffer {
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    pub render_x: usize,
+    pub offset_y: usize,
+    pub offset_x: usize,
+    pub content: Lines<String>,
+    pub highlight_open: Lines<HighlightOpen>,
+    pub filename: String,
+    file_path: String,
+    pub dirty: bool,
+    undo_stack: VecDeque<UndoEntry>,
+    redo_stack: Vec<UndoEntry>,
+    undo_group_open: bool,
+    pub line_ending: LineEnding,
+    pub encoding: Encoding,
+    /// Whether the file ended with a line ending, which isn't kept in `content`.
+    final_newline: bool,
+    pub syntax: Option<&'static Syntax>,
+    /// Whether the text was piped in on stdin, so it's passed on to stdout if not saved.
+    from_stdin: bool,
+    /// When the file was last changed, as of opening or saving it, to notice it being changed
+    /// by something else.
+    modified: Option<SystemTime>,
+    /// The column moving up and down keeps going back to, past lines too short for it, and
+    /// where that left the cursor. Moving the cursor any other way forgets it.
+    goal_x: Option<(usize, (usize, usize))>,
+    /// Where the selection started, if there is one. It runs from here to the cursor.
+    selection_anchor: Option<(usize, usize)>,
+    /// The rest of the file, read as the user gets near it so large files open right away.
+    unread: Option<LineReader>,
+    /// Whether the file was backed up or saved already, so a backup made this session
+    /// isn't overwritten by a later one.
+    backed_up: bool,
+    /// Whether the text can't be changed or saved over the file, which can still be saved
+    /// somewhere else.
+    pub read_only: bool,
+    /// The file lines are read back from once they're forgotten, with when it was last changed
+    /// and how long it was as of opening or saving it, to notice it being written over in
+    /// place. Text piped in has none.
+    opened: Option<(File, Option<SystemTime>, u64)>,
+    /// Whether the file changed before all of it was read, or under lines forgotten to be
+    /// read from it again, so saving over it would lose the rest.
+    partial: bool,
+}
+
+/// The list of recently opened files shown to pick one from, most recent first.
+pub struct RecentFiles {
+    pub paths: Vec<String>,
+    pub selected: usize,
+}
+
+/// A file being read into a buffer a line at a time, counting the line endings seen so far.
+struct LineReader {
+    reader: Box<dyn BufRead>,
+    lf_count: usize,
+    crlf_count: usize,
+    /// How many bytes have been read, out of `size` if how many there are is known.
+    read: u64,
+    size: Option<u64>,
+    /// Where in the file the next line starts, for a file whose lines can be read again from
+    /// there. Text piped in and UTF-16, decoded up front, have none.
+    at: Option<u64>,
+}
+
+/// How the lines of a file are separated, so it's saved the way it was read.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+        }
+    }
+
+    /// The line ending called `name`, `lf` or `crlf` in any case.
+    fn parse(name: &str) -> Option<LineEnding> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            _ => None,
+        }
+    }
+}
+
+/// Whether keys type text or run the commands of the modal mode, like `x` deleting a
+/// character.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Insert,
+    Normal,
+}
+
+/// A single change to the content, with enough information to be reversed.
+/// Columns are counted in characters.
+pub enum EditOp {
+    Insert { x: usize, y: usize, text: String },
+    Delete { x: usize, y: usize, text: String },
+    SplitLine { x: usize, y: usize },
+    JoinLine { x: usize, y: usize },
+    InsertLine { y: usize, line: String },
+    RemoveLine { y: usize, line: String },
+}
+
+/// The changes made by one user action, undone and redone together.
+pub struct UndoEntry {
+    ops: Vec<EditOp>,
+    cursor: (usize, usize),
+}
+
+/// A callback run by `prompt` after every key, with the input so far and the key pressed.
+/// It can change the input, like completing it, and return a new prompt text, to show
+/// something that changed along with the input.
+type PromptCallback<'a> = &'a mut dyn FnMut(&mut Editor, &mut String, u32) -> Option<String>;
+
+impl Editor {
+    /// An editor for a screen of the given size, with no buffers and the terminal left
+    /// untouched.
+    pub fn new(rows: usize, columns: usize) -> Editor {
+        Editor {
+            columns,
+            rows,
+            raw_mode: None,
+            input: InputBuffer::new(),
+            buffers: Vec::new(),
+            active_buffer: 0,
+            tab_stop: DEFAULT_TAB_STOP,
+            expand_tab: false,
+            fill_column: DEFAULT_FILL_COLUMN,
+            show_line_numbers: false,
+            show_whitespace: false,
+            wrap: false,
+            overwrite: false,
+            modal: false,
+            mode: Mode::Insert,
+            cursor_shape: false,
+            pending: None,
+            ensure_final_newline: false,
+            trim_trailing_whitespace: false,
+            make_backup: false,
+            formatters: DEFAULT_FORMATTERS
+                .iter()
+                .map(|&(extension, command)| (String::from(extension), String::from(command)))
+                .collect(),
+            mkdir_on_save: false,
+            duplicate_to_copy: false,
+            read_only: false,
+            status_message: String::new(),
+            status_message_time: Instant::now(),
+            output: Box::new(io::stdout()),
+            drawn_rows: Vec::new(),
+            progress_time: Instant::now(),
+            quit_times: QUIT_TIMES,
+            cancel_pressed: false,
+            undo_limit: DEFAULT_UNDO_LIMIT,
+            pasting: false,
+            pasted_cr: false,
+            register: String::new(),
+            piped_output: None,
+            show_help: false,
+            recent_files: None,
+            keymap: default_keymap(),
+            theme: Theme::default(),
+            color_depth: ColorDepth::detect(),
+            search: Search::default(),
+            searching: false,
+        }
+    }
+
+    /// Reads the `key=value` settings in `~/.config/aricode/config`, if there is one.
+    pub fn load_config(&mut self) {
+        let Ok(home) = env::var("HOME") else {
+            return;
+        };
+        let config_path = Path::new(&home).join(".config/aricode/config");
+
+        let config = match fs::read_to_string(&config_path) {
+            Ok(config) => config,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => {
+                let message = format!("Can't read {}: {}", config_path.display(), err);
+                self.set_status_message(&message);
+                return;
+            }
+        };
+
+        let mut warnings = Vec::new();
+
+        for line in config.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                warnings.push(format!("not a setting: {}", line));
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            // a key binding, like `bind ctrl-s = save`, or `= none` to leave the key unbound
+            if let Some(key_name) = key.strip_prefix("bind ") {
+                let Some(bound) = parse_key(key_name.trim()) else {
+                    warnings.push(format!("unknown key {}", key_name.trim()));
+                    continue;
+                };
+                match parse_action(value) {
+                    Some(action) => {
+                        self.keymap.insert(bound, action);
+                    }
+                    None if value == "none" => {
+                        self.keymap.remove(&bound);
+                    }
+                    None => warnings.push(format!("unknown action {}", value)),
+                }
+                continue;
+            }
+
+            if let Err(warning) = self.apply_setting(key, value) {
+                warnings.push(warning);
+            }
+        }
+
+        if !warnings.is_empty() {
+            self.set_status_message(&format!("Config: {}", warnings.join(", ")));
+        }
+    }
+
+    /// Sets the setting `key` to `value`, as written in the config file, or says why not.
+    fn apply_setting(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let valid = match key {
+            "tab_stop" => value
+                .parse()
+                .ok()
+                .filter(|&width| width > 0)
+                .map(|width| self.tab_stop = width),
+            "expand_tab" => value.parse().ok().map(|expand| self.expand_tab = expand),
+            "fill_column" => value
+                .parse()
+                .ok()
+                .filter(|&width| width > 0)
+                .map(|width| self.fill_column = width),
+            "line_numbers" => value.parse().ok().map(|show| self.show_line_numbers = show),
+            "wrap" => value.parse().ok().map(|wrap| self.wrap = wrap),
+            "show_whitespace" => value.parse().ok().map(|show| self.show_whitespace = show),
+            "ensure_final_newline" => value
+                .parse()
+                .ok()
+                .map(|ensure| self.ensure_final_newline = ensure),
+            "trim_trailing_whitespace" => value
+                .parse()
+                .ok()
+                .map(|trim| self.trim_trailing_whitespace = trim),
+            "make_backup" => value.parse().ok().map(|backup| self.make_backup = backup),
+            "mkdir_on_save" => value.parse().ok().map(|mkdir| self.mkdir_on_save = mkdir),
+            "undo_limit" => value.parse().ok().map(|limit| self.undo_limit = limit),
+            "duplicate_to_copy" => value
+                .parse()
+                .ok()
+                .map(|to_copy| self.duplicate_to_copy = to_copy),
+            "cursor_shape" => value.parse().ok().map(|shape| self.cursor_shape = shape),
+            "modal" => value.parse().ok().map(|modal| {
+                self.modal = modal;
+                self.mode = if modal { Mode::Normal } else { Mode::Insert };
+            }),
+            // the formatter of a kind of file, like `format_rs = rustfmt --edition 2021`, or
+            // nothing to have none
+            _ if key.starts_with("format_") => {
+                let extension = String::from(&key["format_".len()..]);
+                match value.is_empty() {
+                    true => self.formatters.remove(&extension),
+                    false => self.formatters.insert(extension, String::from(value)),
+                };
+                Some(())
+            }
+            // a color of the theme, like `color_keyword = blue`
+            _ => match key
+                .strip_prefix("color_")
+                .and_then(|name| self.theme.color_mut(name))
+            {
+                Some(color) => Color::parse(value).map(|parsed| *color = parsed),
+                None => return Err(format!("unknown setting {}", key)),
+            },
+        };
+
+        valid.ok_or_else(|| format!("bad value for {}: {}", key, value))
+    }
+
+    pub fn open_editor(&mut self) {
+        let args: Vec<String> = env::args().collect();
+
+        // flags are handled before anything is opened, since some exit right away and the
+        // rest change how files are opened
+        for arg in &args[1..] {
+            match arg.as_str() {
+                "-R" | "--readonly" => self.read_only = true,
+                "--version" => {
+                    println!("Ari Code's Editor v{}", VERSION);
+                    process::exit(0);
+                }
+                "--help" => {
+                    self.print_usage();
+                    process::exit(0);
+                }
+                flag if flag.starts_with('-') && flag != "-" => {
+                    eprintln!("Unknown option: {}", flag);
+                    eprintln!("Try --help to see what there is");
+                    process::exit(2);
+                }
+                _ => {}
+            }
+        }
+
+        // a line to start on is given as +42 before the file or as file.rs:42
+        let mut line = None;
+        for arg in &args[1..] {
+            if let Some(number) = arg.strip_prefix('+').and_then(|n| n.parse::<usize>().ok()) {
+                line = Some(number);
+                continue;
+            }
+
+            match arg.as_str() {
+                "-R" | "--readonly" => continue,
+                "-" => self.open_stdin(),
+                _ => {
+                    let (file_path, file_line) = split_line_number(arg);
+                    self.open_file(file_path);
+                    line = line.or(file_line);
+                }
+            }
+
+            if let Some(line) = line.take() {
+                self.place_cursor(0, line.saturating_sub(1));
+            }
+        }
+
+        if self.buffers.is_empty() {
+            self.buffers.push(Buffer {
+                read_only: self.read_only,
+                ..Buffer::default()
+            });
+        }
+
+        self.active_buffer = 0;
+    }
+
+    fn print_usage(&self) {
+        println!("Usage: text-editor [-R] [+line] [file[:line]...]");
+        println!();
+        println!("Opens each file in a buffer of its own, or an empty one without any.");
+        println!("A file of - is the text piped in, which goes on to stdout if not saved.");
+        println!("A line given as +42 before a file or as file:42 is where its cursor starts.");
+        println!();
+        println!("  -R, --readonly  Open the files without letting them be changed");
+        println!("  --help          Show this help");
+        println!("  --version       Show the version");
+        println!();
+        println!("Keys:");
+
+        let bindings = help_lines(&self.keymap);
+        let key_width = bindings
+            .iter()
+            .map(|(keys, _)| keys.len())
+            .max()
+            .unwrap_or(0);
+        for (keys, description) in &bindings {
+            println!("  {:<2$}  {}", keys, description, key_width);
+        }
+    }
+
+    /// Opens `file_path` in a new buffer, which becomes the current one.
+    pub fn open_file(&mut self, file_path: &str) {
+        self.buffers.push(Buffer::default());
+        self.active_buffer = self.buffers.len() - 1;
+        self.load_file(file_path);
+    }
+
+    /// Reloads the file if something else changed it, or asks first if that would throw away
+    /// unsaved changes.
+    fn check_file_changed(&mut self) {
+        let buffer = self.current_buffer_mut();
+        if buffer.modified.is_none() {
+            return;
+        }
+
+        let modified = modified_time(&buffer.file_path);
+        if modified == buffer.modified {
+            return;
+        }
+        // whatever the answer, this change has been dealt with
+        buffer.modified = modified;
+
+        if modified.is_none() {
+            self.set_status_message("File no longer on disk, saving will write it again");
+            return;
+        }
+
+        if buffer.dirty {
+            let answer = self.prompt(
+                "File changed on disk, reload and lose your changes? (y/n) {}",
+                None,
+            );
+            if !answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y")) {
+                self.set_status_message("Keeping your changes");
+                return;
+            }
+        }
+
+        let buffer = self.current_buffer();
+        let (file_path, cursor) = (buffer.file_path.clone(), (buffer.cursor_x, buffer.cursor_y));
+
+        *self.current_buffer_mut() = Buffer::default();
+        self.load_file(&file_path);
+
+        self.place_cursor(cursor.0, cursor.1);
+        self.set_status_message("File reloaded");
+    }
+
+    /// Reads the text piped in on stdin into a new buffer, then takes the keys from the
+    /// terminal instead.
+    fn open_stdin(&mut self) {
+        // once the terminal has taken over there's nothing left to read
+        if self.piped_output.is_some() {
+            return;
+        }
+
+        let mut text = Vec::new();
+        if let Err(err) = io::stdin().read_to_end(&mut text) {
+            self.die(io::Error::new(
+                err.kind(),
+                format!("Can't read stdin: {}", err),
+            ));
+        }
+        match attach_to_terminal() {
+            Ok(output) => self.piped_output = Some(output),
+            Err(err) => self.die(io::Error::new(err.kind(), format!("No terminal: {}", err))),
+        }
+
+        let size = text.len() as u64;
+        // it's all here already, so it's known up front whether it's UTF-8
+        let utf8 = std::str::from_utf8(&text).is_ok();
+        let (reader, mut encoding) = match open_reader(Box::new(Cursor::new(text))) {
+            Ok(opened) => opened,
+            Err(err) => self.die(err),
+        };
+        if encoding == Encoding::Utf8 && !utf8 {
+            encoding = Encoding::Latin1;
+            self.set_status_message("Not valid UTF-8, reading it as Latin-1");
+        }
+
+        self.buffers.push(Buffer::default());
+        self.active_buffer = self.buffers.len() - 1;
+
+        let read_only = self.read_only;
+        let buffer = self.current_buffer_mut();
+        buffer.filename = String::from("[stdin]");
+        buffer.from_stdin = true;
+        buffer.read_only = read_only;
+        buffer.encoding = encoding;
+        buffer.unread = Some(LineReader {
+            reader,
+            lf_count: 0,
+            crlf_count: 0,
+            read: 0,
+            size: utf8_size(encoding, size),
+            at: None,
+        });
+        self.load_lines(usize::MAX);
+    }
+
+    /// Reads `file_path` into the current buffer, which is expected to be empty.
+    fn load_file(&mut self, file_path: &str) {
+        let file = File::open(file_path);
+
+        // remember the path even if it can't be read, so saving goes where the user asked
+        let read_only = self.read_only;
+        let buffer = self.current_buffer_mut();
+        buffer.filename = String::from(extract_filename(file_path));
+        buffer.file_path = String::from(file_path);
+        buffer.syntax = detect_syntax(file_path);
+        buffer.read_only = read_only;
+
+        match file {
+            Ok(f) => {
+                // opening it for writing doesn't change it, and tells whether it could be saved
+                if let Err(err) = OpenOptions::new().write(true).open(file_path) {
+                    if err.kind() == io::ErrorKind::PermissionDenied {
+                        self.current_buffer_mut().read_only = true;
+                    }
+                }
+                let metadata = f.metadata().ok();
+                let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+                self.current_buffer_mut().modified = modified;
+                let size = metadata.map_or(0, |m| m.len());
+                let opened =
+                    open_reader(Box::new(BufReader::new(f))).and_then(|opened| match opened.1 {
+                        Encoding::Utf8 => Ok((opened, starts_as_utf8(File::open(file_path)?)?)),
+                        _ => Ok((opened, true)),
+                    });
+                let ((reader, mut encoding), utf8) = match opened {
+                    Ok(opened) => opened,
+                    Err(err) => {
+                        self.set_status_message(&format!("Can't read {}: {}", file_path, err));
+                        return;
+                    }
+                };
+                if !utf8 {
+                    encoding = Encoding::Latin1;
+                    self.set_status_message("Not valid UTF-8, reading it as Latin-1");
+                }
+
+                let buffer = self.current_buffer_mut();
+                buffer.encoding = encoding;
+                buffer.unread = Some(LineReader {
+                    reader,
+                    lf_count: 0,
+                    crlf_count: 0,
+                    read: 0,
+                    size: utf8_size(encoding, size),
+                    at: encoding
+                        .reads_in_place()
+                        .then_some(encoding.bom().len() as u64),
+                });
+                if let Some(source) = buffer.open_lines(file_path, None) {
+                    buffer.content.set_source(source);
+                }
+                self.load_lines(LOAD_AHEAD_LINES);
+                self.restore_position();
+                remember_recent_file(file_path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.set_status_message(&format!("New file: {}", file_path));
+            }
+            Err(err) => {
+                self.set_status_message(&format!("Can't open {}: {}", file_path, err));
+            }
+        }
+    }
+
+    /// Asks for a path and opens it in place of the current buffer.
+    fn open_prompt(&mut self) {
+        if self.current_buffer().dirty {
+            let answer = self.prompt("Discard unsaved changes? (y/n) {}", None);
+            if !answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y")) {
+                self.set_status_message("Open aborted");
+                return;
+            }
+        }
+
+        // Tab completes the path, and pressed again goes on to the next file that fits
+        let mut completions: Vec<String> = Vec::new();
+        let mut completed = 0;
+        let mut on_key = |_: &mut Editor, input: &mut String, key: u32| {
+            if key != TAB {
+                completions.clear();
+                return Some(String::from(OPEN_PROMPT));
+            }
+
+            // a single match is completed further instead, like into a directory
+            if completions.len() > 1 && completions.get(completed) == Some(&*input) {
+                completed = (completed + 1) % completions.len();
+            } else {
+                completions = complete_path(input);
+                completed = 0;
+            }
+
+            let Some(completion) = completions.get(completed) else {
+                return Some(format!("{} [no matches]", OPEN_PROMPT));
+            };
+            *input = completion.clone();
+            if completions.len() == 1 {
+                return Some(String::from(OPEN_PROMPT));
+            }
+
+            let names: Vec<&str> = completions
+                .iter()
+                .map(|path| extract_filename(path.trim_end_matches('/')))
+                .collect();
+            Some(format!("{} [{}]", OPEN_PROMPT, names.join(" ")))
+        };
+
+        let Some(file_path) = self.prompt(OPEN_PROMPT, Some(&mut on_key)) else {
+            self.set_status_message("Open aborted");
+            return;
+        };
+
+        *self.current_buffer_mut() = Buffer::default();
+        self.load_file(&file_path);
+    }
+
+    /// Asks for a command, like `w` to save or `e path` to open a file, and runs it.
+    fn command_prompt(&mut self) {
+        if let Some(command) = self.prompt(":{} (ESC to cancel)", None) {
+            self.run_command(command.trim_start_matches(':'));
+        }
+    }
+
+    fn run_command(&mut self, command: &str) {
+        let (name, argument) = match command.trim().split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (command.trim(), None),
+        };
+
+        match (name, argument) {
+            ("w", None) => self.save_file(),
+            ("w", Some(file_path)) => self.save_file_as(file_path),
+            ("q", None) => self.quit(),
+            ("q!", None) => self.exit(),
+            ("wq", None) => {
+                self.save_file();
+                // a save that failed leaves the changes unsaved, so they aren't lost by quitting
+                if !self.current_buffer().dirty {
+                    self.quit();
+                }
+            }
+            ("e", None) => self.open_prompt(),
+            ("e", Some(_)) if self.current_buffer().dirty => {
+                self.set_status_message("Unsaved changes, use e! to discard them");
+            }
+            ("e" | "e!", Some(file_path)) => {
+                *self.current_buffer_mut() = Buffer::default();
+                self.load_file(file_path);
+            }
+            ("set", Some(setting)) => {
+                // `name` turns a setting on and `noname` off, like in vi
+                let (key, value) = match setting.split_once('=') {
+                    Some((key, value)) => (key.trim(), value.trim()),
+                    None => match setting.strip_prefix("no") {
+                        Some(key) => (key, "false"),
+                        None => (setting, "true"),
+                    },
+                };
+                let key = match key {
+                    "number" => "line_numbers",
+                    key => key,
+                };
+                let applied = match key {
+                    "line_ending" | "encoding" => self.set_file_format(key, value),
+                    _ => self.apply_setting(key, value),
+                };
+                if let Err(warning) = applied {
+                    self.set_status_message(&warning);
+                }
+            }
+            _ => self.set_status_message(&format!("Unknown command: {}", command.trim())),
+        }
+    }
+
+    /// Sets how the current buffer is saved from now on, its `line_ending` or `encoding`. A
+    /// change marks the buffer changed, so saving writes the file again.
+    fn set_file_format(&mut self, key: &str, value: &str) -> Result<(), String> {
+        // the line ending is settled once the file is read, which would undo this one
+        self.load_lines(usize::MAX);
+
+        let buffer = self.current_buffer_mut();
+        let changed = match key {
+            "line_ending" => {
+                let line_ending = LineEnding::parse(value)
+                    .ok_or_else(|| format!("bad value for {}: {}", key, value))?;
+                let changed = line_ending != buffer.line_ending;
+                buffer.line_ending = line_ending;
+                changed
+            }
+            _ => {
+                let encoding = Encoding::parse(value)
+                    .ok_or_else(|| format!("bad value for {}: {}", key, value))?;
+                let changed = encoding != buffer.encoding;
+                buffer.encoding = encoding;
+                changed
+            }
+        };
+
+        if changed {
+            buffer.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Shows the recently opened files that are still there, to pick one to open.
+    fn show_recent_files(&mut self) {
+        let paths: Vec<String> = read_recent_files()
+            .into_iter()
+            .filter(|path| Path::new(path).exists())
+            .collect();
+
+        match paths.is_empty() {
+            true => self.set_status_message("No recent files"),
+            false => self.recent_files = Some(RecentFiles { paths, selected: 0 }),
+        }
+    }
+
+    /// Moves through the recent files with the arrows and opens the one picked with Enter.
+    /// Any other key closes the list.
+    fn recent_files_key(&mut self, key: u32) {
+        let Some(recent) = self.recent_files.as_mut() else {
+            return;
+        };
+
+        match key {
+            ARROW_UP => recent.selected = recent.selected.saturating_sub(1),
+            ARROW_DOWN => recent.selected = (recent.selected + 1).min(recent.paths.len() - 1),
+            ENTER => {
+                let path = recent.paths.swap_remove(recent.selected);
+                self.recent_files = None;
+
+                // a file that's open already is switched to rather than opened twice
+                let key = position_key(&path);
+                match self
+                    .buffers
+                    .iter()
+                    .position(|buffer| position_key(&buffer.file_path) == key)
+                {
+                    Some(i) => self.active_buffer = i,
+                    None => self.open_file(&path),
+                }
+            }
+            _ => self.recent_files = None,
+        }
+    }
+
+    pub fn current_buffer(&self) -> &Buffer {
+        &self.buffers[self.active_buffer]
+    }
+
+    pub fn current_buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.active_buffer]
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Save => self.save_file(),
+            Action::Quit => self.quit(),
+            Action::Cancel => {}
+            Action::Open => self.open_prompt(),
+            Action::Find => self.find(),
+            Action::Replace => self.replace(),
+            Action::GotoLine => self.goto_line(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::CutToLineEnd => self.cut_to_end_of_line(),
+            Action::DeleteLine => self.delete_line(),
+            Action::Duplicate => self.duplicate_lines(),
+            Action::MoveLineUp => self.move_lines(true),
+            Action::MoveLineDown => self.move_lines(false),
+            Action::Paste => {
+                self.delete_selection();
+                self.paste_register();
+            }
+            Action::MatchingBracket => self.jump_to_matching_bracket(),
+            Action::LineNumbers => self.show_line_numbers = !self.show_line_numbers,
+            Action::Wrap => self.wrap = !self.wrap,
+            Action::Whitespace => self.show_whitespace = !self.show_whitespace,
+            Action::Overwrite => self.overwrite = !self.overwrite,
+            Action::Help => self.show_help = true,
+            Action::RecentFiles => self.show_recent_files(),
+            Action::NextBuffer => self.switch_buffer(CTRL_PAGE_DOWN),
+            Action::PreviousBuffer => self.switch_buffer(CTRL_PAGE_UP),
+            Action::Up => self.move_cursor(ARROW_UP),
+            Action::Down => self.move_cursor(ARROW_DOWN),
+            Action::Left => self.move_cursor(ARROW_LEFT),
+            Action::Right => self.move_cursor(ARROW_RIGHT),
+            Action::WordLeft => self.move_cursor(CTRL_ARROW_LEFT),
+            Action::WordRight => self.move_cursor(CTRL_ARROW_RIGHT),
+            Action::LineStart => self.move_cursor(HOME),
+            Action::LineEnd => self.move_cursor(END),
+            Action::FileStart => self.move_cursor(CTRL_HOME),
+            Action::FileEnd => self.move_cursor(CTRL_END),
+            Action::PageUp => self.move_cursor(PAGE_UP),
+            Action::PageDown => self.move_cursor(PAGE_DOWN),
+            Action::SelectUp => self.extend_selection(ARROW_UP),
+            Action::SelectDown => self.extend_selection(ARROW_DOWN),
+            Action::SelectLeft => self.extend_selection(ARROW_LEFT),
+            Action::SelectRight => self.extend_selection(ARROW_RIGHT),
+            Action::Newline => {
+                self.delete_selection();
+                self.insert_newline();
+            }
+            Action::Backspace => {
+                if !self.delete_selection() {
+                    self.delete_char();
+                }
+            }
+            Action::Delete => {
+                if !self.delete_selection() {
+                    self.delete_char_under_cursor();
+                }
+            }
+            Action::Tab => {
+                if self.selected_lines().is_some() {
+                    self.indent_lines(true);
+                } else {
+                    self.delete_selection();
+                    self.insert_tab();
+                }
+            }
+            Action::Dedent => self.indent_lines(false),
+            Action::ToggleComment => self.toggle_comment(),
+            Action::Rewrap => self.rewrap_paragraph(),
+            Action::Format => self.format_buffer(),
+            Action::Stats => self.show_stats(),
+            Action::Command => self.command_prompt(),
+        }
+    }
+
+    fn switch_buffer(&mut self, key: u32) {
+        let count = self.buffers.len();
+
+        self.active_buffer = if key == CTRL_PAGE_DOWN {
+            (self.active_buffer + 1) % count
+        } else {
+            (self.active_buffer + count - 1) % count
+        };
+    }
+
+    /// Shows how a long operation that began at `started` is getting on, in the message bar
+    /// without drawing the rest of the screen. Quick ones finish before anything is shown.
+    fn show_progress(&mut self, started: Instant, progress: &str) {
+        if self.raw_mode.is_none()
+            || started.elapsed() < PROGRESS_INTERVAL
+            || self.progress_time.elapsed() < PROGRESS_INTERVAL
+        {
+            return;
+        }
+
+        self.progress_time = Instant::now();
+        let spinner = SPINNER[(started.elapsed().as_millis() / 100) as usize % SPINNER.len()];
+        self.set_status_message(&format!("{} {}", spinner, progress));
+        refresh_message_bar(self);
+    }
+
+    fn set_status_message(&mut self, message: &str) {
+        self.status_message = String::from(message);
+        self.status_message_time = Instant::now();
+    }
+
+    pub fn handle_key(&mut self, key: u32) {
+        // the keys that aren't typed or bound go first, and only then is the rest looked up in
+        // the keymap or typed as a character
+        match key {
+            WINDOW_RESIZED => return,
+            IDLE => {
+                self.check_file_changed();
+                return;
+            }
+            _ if self.pasting => {
+                self.paste_key(key);
+                return;
+            }
+            // any key closes the help, without doing anything else
+            _ if self.show_help => {
+                self.show_help = false;
+                return;
+            }
+            _ if self.recent_files.is_some() => {
+                self.recent_files_key(key);
+                return;
+            }
+            MOUSE_CLICK => {
+                let (row, column) = self.input.mouse;
+                self.click(row, column);
+            }
+            MOUSE_WHEEL_UP => self.scroll_view(-(WHEEL_SCROLL_LINES as isize)),
+            MOUSE_WHEEL_DOWN => self.scroll_view(WHEEL_SCROLL_LINES as isize),
+            // the paste still has to be read, so its keys are what's thrown away
+            PASTE_START if self.current_buffer().read_only => {
+                self.refuse_edit();
+                self.pasting = true;
+            }
+            PASTE_START if self.mode == Mode::Normal => self.pasting = true,
+            PASTE_START => {
+                self.delete_selection();
+                self.pasting = true;
+            }
+            // dropping the selection is left to what follows, as after any key that isn't
+            // selecting
+            ESCAPE => {
+                self.set_status_message("");
+                if self.modal {
+                    self.mode = Mode::Normal;
+                }
+            }
+            _ => {}
+        }
+
+        let action = self.keymap.get(&key).copied();
+        // whatever key comes after the first of a two key command ends it
+        let pending = self.pending.take();
+
+        // consecutive typing is undone in one go, anything else starts a new undo step
+        if action.is_some() || printable_char(key).is_none() || self.mode == Mode::Normal {
+            self.current_buffer_mut().undo_group_open = false;
+        }
+
+        // indenting and commenting keep the selection, so they can be done again to the same
+        // lines
+        let selecting = matches!(
+            action,
+            Some(
+                Action::SelectUp
+                    | Action::SelectDown
+                    | Action::SelectLeft
+                    | Action::SelectRight
+                    | Action::Tab
+                    | Action::Dedent
+                    | Action::ToggleComment
+                    | Action::Duplicate
+                    | Action::MoveLineUp
+                    | Action::MoveLineDown
+            )
+        );
+
+        match action {
+            Some(Action::Quit) => {
+                self.quit();
+                return;
+            }
+            Some(Action::Cancel) => {
+                // a selection or a half typed command is what's cancelled, and only with
+                // neither does a second press quit
+                let selected = self.current_buffer_mut().selection_anchor.take().is_some();
+                if selected || pending.is_some() {
+                    self.cancel_pressed = false;
+                    self.set_status_message("");
+                } else if self.cancel_pressed {
+                    self.quit();
+                } else {
+                    self.cancel_pressed = true;
+                    self.set_status_message("Press Ctrl-C again to quit");
+                }
+                return;
+            }
+            // normal mode only changes the text with its own commands, and Enter and Backspace
+            // move like they do in vi
+            Some(Action::Newline) if self.mode == Mode::Normal => self.move_to_next_line(),
+            Some(Action::Backspace) if self.mode == Mode::Normal => self.move_cursor(ARROW_LEFT),
+            Some(Action::Undo | Action::Redo) if self.current_buffer().read_only => {
+                self.refuse_edit();
+            }
+            Some(action @ (Action::Undo | Action::Redo)) => self.run_action(action),
+            Some(action) if action.edits() && self.mode == Mode::Normal => {}
+            Some(action) if action.edits() && self.current_buffer().read_only => {
+                self.refuse_edit();
+            }
+            Some(action) => self.run_action(action),
+            None => {
+                if let Some(c) = printable_char(key).filter(|_| self.mode == Mode::Normal) {
+                    self.normal_key(c, pending);
+                } else if printable_char(key).is_some() && self.current_buffer().read_only {
+                    self.refuse_edit();
+                } else if let Some(c) = printable_char(key) {
+                    // typing over a selection only replaces the selection, even when overwriting
+                    if !self.delete_selection() && self.overwrite {
+                        self.overwrite_char(c);
+                    } else {
+                        self.insert_char(c);
+                    }
+                }
+            }
+        }
+
+        if !selecting {
+            self.current_buffer_mut().selection_anchor = None;
+        }
+
+        self.quit_times = QUIT_TIMES;
+        if self.cancel_pressed {
+            self.cancel_pressed = false;
+            self.set_status_message("");
+        }
+    }
+
+    /// Runs the normal mode command for `c`, or the second half of the one `pending` started.
+    /// Other keys do nothing, rather than typing.
+    fn normal_key(&mut self, c: char, pending: Option<char>) {
+        match (pending, c) {
+            (Some('d'), 'd') | (_, 'x' | 'u') if self.current_buffer().read_only => {
+                self.refuse_edit();
+            }
+            (Some('d'), 'd') => self.delete_line(),
+            (_, 'd') => self.pending = Some('d'),
+            (_, 'h') => self.move_cursor(ARROW_LEFT),
+            (_, 'j') => self.move_cursor(ARROW_DOWN),
+            (_, 'k') => self.move_cursor(ARROW_UP),
+            (_, 'l') => self.move_cursor(ARROW_RIGHT),
+            (_, 'i') => self.mode = Mode::Insert,
+            (_, 'u') => self.undo(),
+            // unlike Delete, x stays on its line instead of joining the next one
+            (_, 'x') if self.current_buffer().cursor_x < self.current_line_len() => {
+                self.delete_char_under_cursor();
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves to the start of the text on the next line, like Enter in vi's normal mode.
+    fn move_to_next_line(&mut self) {
+        let buffer = self.current_buffer_mut();
+        let Some(line) = buffer.content.get(buffer.cursor_y + 1) else {
+            return;
+        };
+
+        buffer.cursor_x = line.chars().take_while(|c| c.is_whitespace()).count();
+        buffer.cursor_y += 1;
+    }
+
+    /// Says the text can't be changed, in place of changing it.
+    fn refuse_edit(&mut self) {
+        self.set_status_message("Read-only, the text can't be changed");
+    }
+
+    /// Quits, unless there are unsaved changes and this wasn't pressed enough times in a row.
+    fn quit(&mut self) {
+        let dirty = self.buffers.iter().any(|buffer| buffer.dirty);
+
+        if dirty && self.quit_times > 1 {
+            self.quit_times -= 1;
+            let message = format!(
+                "File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                self.quit_times
+            );
+            self.set_status_message(&message);
+            return;
+        }
+
+        self.exit();
+    }
+
+    /// Inserts a key of pasted text as is, without auto-indent or key bindings. The whole paste
+    /// is undone in one step.
+    fn paste_key(&mut self, key: u32) {
+        let after_cr = std::mem::replace(&mut self.pasted_cr, key == ENTER);
+        match key {
+            PASTE_END => self.pasting = false,
+            _ if self.current_buffer().read_only || self.mode == Mode::Normal => {}
+            0x0a if after_cr => {}
+            ENTER | 0x0a => self.break_line(),
+            TAB => self.insert_text("\t"),
+            _ => {
+                if let Some(c) = printable_char(key) {
+                    self.insert_text(&c.to_string());
+                }
+            }
+        }
+    }
+
+    /// Puts the cursor back where it was when the file was last closed, if it's still there.
+    fn restore_position(&mut self) {
+        let key = position_key(&self.current_buffer().file_path);
+        let Some((_, x, y)) = read_positions().into_iter().find(|(path, ..)| *path == key) else {
+            return;
+        };
+
+        self.place_cursor(x, y);
+    }
+
+    /// Moves the cursor to `(x, y)`, or as close as the file allows, for a position kept from
+    /// before the file was last read or one asked for on the command line.
+    fn place_cursor(&mut self, x: usize, y: usize) {
+        self.load_lines(y + 1);
+        let buffer = self.current_buffer_mut();
+        buffer.cursor_y = y.min(buffer.content.len().saturating_sub(1));
+        buffer.cursor_x = match buffer.content.get(buffer.cursor_y) {
+            Some(line) => x.min(char_count(line)),
+            None => 0,
+        };
+    }
+
+    /// Records where the cursor is in each open file, for `restore_position`.
+    fn save_positions(&self) {
+        let Some(path) = positions_path() else {
+            return;
+        };
+
+        let mut positions = read_positions();
+        for buffer in self
+            .buffers
+            .iter()
+            .filter(|buffer| !buffer.file_path.is_empty())
+        {
+            let key = position_key(&buffer.file_path);
+            positions.retain(|(path, ..)| *path != key);
+            positions.push((key, buffer.cursor_x, buffer.cursor_y));
+        }
+
+        // the most recently closed files are at the end, and the oldest ones are forgotten
+        let skip = positions.len().saturating_sub(MAX_SAVED_POSITIONS);
+        let text: String = positions[skip..]
+            .iter()
+            .map(|(path, x, y)| format!("{} {} {}\n", y, x, path))
+            .collect();
+
+        // there's nowhere left to report a failure, and it only costs a cursor position
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(path, text);
+    }
+
+    /// Reads lines from the file still being opened until the buffer has at least `count` of
+    /// them, or the file is done.
+    pub fn load_lines(&mut self, count: usize) {
+        let dirty = self.current_buffer().dirty;
+        let started = Instant::now();
+
+        // what's left to read of a file written over in place belongs to some other text, and
+        // so do the lines forgotten to be read from it again
+        let changed =
+            self.current_buffer()
+                .opened
+                .as_ref()
+                .is_some_and(|(file, modified, size)| {
+                    file.metadata().map_or(true, |metadata| {
+                        metadata.modified().ok() != *modified || metadata.len() != *size
+                    })
+                });
+        if changed {
+            let buffer = self.current_buffer_mut();
+            buffer.opened = None;
+            if buffer.unread.is_some() || buffer.content.has_forgotten() {
+                buffer.partial = true;
+                let message = match buffer.unread.is_some() {
+                    true => "File changed on disk before it was all read, keeping what was",
+                    false => "File changed on disk, lines read back from it may be wrong",
+                };
+                self.finish_loading();
+                self.set_status_message(message);
+            }
+        }
+
+        while self.current_buffer().content.len() < count {
+            let buffer = self.current_buffer_mut();
+            let Some(unread) = buffer.unread.as_mut() else {
+                break;
+            };
+
+            let mut bytes = Vec::new();
+            match unread.reader.read_until(b'\n', &mut bytes) {
+                Ok(0) => {
+                    self.finish_loading();
+                    break;
+                }
+                Ok(read) => {
+                    unread.read += read as u64;
+                    let stored = unread.at.map(|at| at..at + read as u64);
+                    unread.at = stored.as_ref().map(|stored| stored.end);
+                    let progress = match unread.size {
+                        Some(size) => format!("Loading... {}%", percent(unread.read, size)),
+                        None => String::from("Loading..."),
+                    };
+                    self.show_progress(started, &progress);
+
+                    let buffer = self.current_buffer_mut();
+                    let was_utf8 = buffer.encoding == Encoding::Utf8;
+                    let mut line = decode_line(&mut buffer.encoding, bytes);
+                    if was_utf8 && buffer.encoding == Encoding::Latin1 {
+                        self.read_again_as_latin1();
+                        self.set_status_message("Not valid UTF-8, reading it as Latin-1");
+                    }
+
+                    let buffer = self.current_buffer_mut();
+                    let ending = strip_line_ending(&mut line, None);
+                    let unread = buffer.unread.as_mut().unwrap();
+                    match ending {
+                        Some(LineEnding::CrLf) => unread.crlf_count += 1,
+                        Some(LineEnding::Lf) => unread.lf_count += 1,
+                        None => {}
+                    }
+                    buffer.final_newline = ending.is_some();
+                    let at = buffer.content.len();
+                    match stored {
+                        Some(stored) => {
+                            buffer.content.push_stored(line, stored);
+                            self.row_inserted(at);
+                        }
+                        None => self.insert_row(at, line),
+                    }
+
+                    // a file read through to the end keeps no more of it than scrolling does
+                    if (at + 1).is_multiple_of(KEPT_LINES) {
+                        let rows = self.rows;
+                        self.current_buffer_mut().forget_far_lines(rows);
+                    }
+                }
+                Err(err) => {
+                    let message =
+                        format!("Can't read {}: {}", self.current_buffer().file_path, err);
+                    self.finish_loading();
+                    self.set_status_message(&message);
+                    break;
+                }
+            }
+        }
+
+        // lines coming in from the file aren't changes
+        self.current_buffer_mut().dirty = dirty;
+    }
+
+    /// Reads the lines read so far again as Latin-1, for a file that turned out not to be
+    /// UTF-8 past the start checked when it was opened.
+    fn read_again_as_latin1(&mut self) {
+        let buffer = self.current_buffer_mut();
+
+        // a character's column becomes that of its first byte, every byte being one now
+        let column = |buffer: &Buffer, (x, y): (usize, usize)| match buffer.content.get(y) {
+            Some(line) => (byte_index(line, x), y),
+            None => (x, y),
+        };
+        buffer.cursor_x = column(buffer, (buffer.cursor_x, buffer.cursor_y)).0;
+        buffer.selection_anchor = buffer.selection_anchor.map(|anchor| column(buffer, anchor));
+        buffer.goal_x = None;
+        // the changes made so far are to text that's no longer there
+        buffer.undo_stack.clear();
+        buffer.redo_stack.clear();
+
+        let source = buffer
+            .opened
+            .as_ref()
+            .and_then(|(file, ..)| file.try_clone().ok())
+            .map(|file| line_source(file, Encoding::Latin1, None));
+        buffer
+            .content
+            .reread(source, |line| *line = latin1(line.as_bytes()));
+    }
+
+    /// Stops reading the file and settles on the line ending it used most.
+    fn finish_loading(&mut self) {
+        let buffer = self.current_buffer_mut();
+        let Some(unread) = buffer.unread.take() else {
+            return;
+        };
+
+        if unread.crlf_count > unread.lf_count {
+            buffer.line_ending = LineEnding::CrLf;
+        }
+
+        if unread.lf_count > 0 && unread.crlf_count > 0 {
+            let message = format!(
+                "Mixed line endings, will save with {}",
+                buffer.line_ending.name()
+            );
+            self.set_status_message(&message);
+        }
+    }
+
+    fn insert_row(&mut self, at: usize, line: String) {
+        self.current_buffer_mut().content.insert(at, line);
+        self.row_inserted(at);
+    }
+
+    /// Highlights the line just added to the content at `at`.
+    fn row_inserted(&mut self, at: usize) {
+        let tab_stop = self.tab_stop;
+        let buffer = self.current_buffer_mut();
+
+        // only highlighted files keep what each line leaves open. A new line starts out
+        // leaving open whatever its following line used to inherit.
+        if buffer.syntax.is_some() {
+            let open = match at {
+                0 => HighlightOpen::Closed,
+                _ => buffer.highlight_open[at - 1],
+            };
+            buffer.highlight_open.insert(at, open);
+        }
+        buffer.dirty = true;
+        update_highlight(buffer, at, tab_stop);
+    }
+
+    fn delete_row(&mut self, at: usize) -> String {
+        let tab_stop = self.tab_stop;
+        let buffer = self.current_buffer_mut();
+
+        if buffer.syntax.is_some() {
+            buffer.highlight_open.remove(at);
+        }
+        buffer.dirty = true;
+        let line = buffer.content.remove(at);
+        update_highlight(buffer, at, tab_stop);
+        line
+    }
+
+    fn update_row(&mut self, at: usize) {
+        let tab_stop = self.tab_stop;
+        let buffer = self.current_buffer_mut();
+
+        buffer.dirty = true;
+        update_highlight(buffer, at, tab_stop);
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.insert_text(&c.to_string());
+
+        if c.is_whitespace() {
+            // keep undo steps about a word long
+            self.current_buffer_mut().undo_group_open = false;
+        }
+    }
+
+    /// Types `c` in place of the character under the cursor, or adds it like `insert_char`
+    /// at the end of the line.
+    fn overwrite_char(&mut self, c: char) {
+        let buffer = self.current_buffer();
+        let (x, y) = (buffer.cursor_x, buffer.cursor_y);
+
+        if let Some(under) = buffer.content.get(y).and_then(|line| line.chars().nth(x)) {
+            self.edit(EditOp::Delete {
+                x,
+                y,
+                text: under.to_string(),
+            });
+        }
+
+        self.insert_char(c);
+    }
+
+    /// Inserts a tab, or with `expand_tab` the spaces up to the next tab stop.
+    fn insert_tab(&mut self) {
+        if !self.expand_tab {
+            self.insert_char('\t');
+            return;
+        }
+
+        let render_x = self.cursor_render_x();
+        let spaces = self.tab_stop - (render_x % self.tab_stop);
+        self.insert_text(" ".repeat(spaces).as_str());
+        self.current_buffer_mut().undo_group_open = false;
+    }
+
+    fn cursor_render_x(&self) -> usize {
+        let buffer = self.current_buffer();
+
+        match buffer.content.get(buffer.cursor_y) {
+            Some(line) => cursor_x_to_render_x(line, buffer.cursor_x, self.tab_stop),
+            None => 0,
+        }
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        if self.current_buffer().cursor_y >= self.current_buffer().content.len() {
+            let y = self.current_buffer().content.len();
+            self.edit(EditOp::InsertLine {
+                y,
+                line: String::new(),
+            });
+        }
+
+        let at = self.current_buffer().cursor_x.min(self.current_line_len());
+        let y = self.current_buffer().cursor_y;
+
+        self.edit(EditOp::Insert {
+            x: at,
+            y,
+            text: String::from(text),
+        });
+
+        self.current_buffer_mut().cursor_x = at + char_count(text);
+    }
+
+    fn insert_newline(&mut self) {
+        let at = self.current_buffer().cursor_x.min(self.current_line_len());
+        let indent = self.new_line_indent(at);
+
+        self.break_line();
+
+        if !indent.is_empty() {
+            self.insert_text(&indent);
+        }
+        // what's typed on the new line is undone on its own, leaving the line break
+        self.current_buffer_mut().undo_group_open = false;
+    }
+
+    /// Cuts from the cursor to the end of the line into the register.
+    fn cut_to_end_of_line(&mut self) {
+        if self.current_buffer().cursor_y >= self.current_buffer().content.len() {
+            return;
+        }
+
+        let line_len = self.current_line_len();
+        let x = self.current_buffer().cursor_x.min(line_len);
+        let y = self.current_buffer().cursor_y;
+
+        // at the end of the line there's nothing to cut, and the next line isn't joined on
+        if x == line_len {
+            return;
+        }
+
+        let text: String = self.current_buffer().content[y].chars().skip(x).collect();
+        self.register = text.clone();
+        self.edit(EditOp::Delete { x, y, text });
+
+        self.current_buffer_mut().cursor_x = x;
+    }
+
+    /// Deletes the cursor's line, leaving the cursor at the start of the line that took its
+    /// place.
+    fn delete_line(&mut self) {
+        let y = self.current_buffer().cursor_y;
+        let len = self.current_buffer().content.len();
+        if y >= len {
+            return;
+        }
+
+        // the only line is emptied, since a file always has one
+        let line = self.current_buffer().content[y].clone();
+        if len == 1 {
+            self.edit(EditOp::Delete {
+                x: 0,
+                y,
+                text: line,
+            });
+        } else {
+            self.edit(EditOp::RemoveLine { y, line });
+        }
+
+        let buffer = self.current_buffer_mut();
+        buffer.cursor_y = y.min(buffer.content.len().saturating_sub(1));
+        buffer.cursor_x = 0;
+    }
+
+    /// Inserts the register at the cursor, breaking the line wherever it holds a newline.
+    fn paste_register(&mut self) {
+        let register = self.register.clone();
+
+        for (i, text) in register.split('\n').enumerate() {
+            if i > 0 {
+                self.break_line();
+            }
+            if !text.is_empty() {
+                self.insert_text(text);
+            }
+        }
+    }
+
+    /// Splits the line at the cursor and moves the cursor to the start of the new line.
+    fn break_line(&mut self) {
+        // an empty buffer still shows a line to break, which has to exist first
+        if self.current_buffer().cursor_y >= self.current_buffer().content.len() {
+            let y = self.current_buffer().content.len();
+            self.edit(EditOp::InsertLine {
+                y,
+                line: String::new(),
+            });
+        }
+
+        let at = self.current_buffer().cursor_x.min(self.current_line_len());
+        let y = self.current_buffer().cursor_y;
+
+        if at == 0 {
+            self.edit(EditOp::InsertLine {
+                y,
+                line: String::new(),
+            });
+        } else {
+            self.edit(EditOp::SplitLine { x: at, y });
+        }
+
+        let buffer = self.current_buffer_mut();
+        buffer.cursor_y += 1;
+        buffer.cursor_x = 0;
+    }
+
+    /// The indentation for a line split off the current one at column `at`: the same as the
+    /// current line, one level deeper after a `{`. Blank lines give no indentation.
+    fn new_line_indent(&self, at: usize) -> String {
+        let buffer = self.current_buffer();
+        let Some(line) = buffer.content.get(buffer.cursor_y) else {
+            return String::new();
+        };
+
+        if line.trim().is_empty() {
+            return String::new();
+        }
+
+        let before_cursor = &line[..byte_index(line, at)];
+        let mut indent: String = before_cursor
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect();
+
+        if before_cursor.trim_end().ends_with('{') {
+            if self.expand_tab {
+                indent.push_str(" ".repeat(self.tab_stop).as_str());
+            } else {
+                indent.push('\t');
+            }
+        }
+
+        indent
+    }
+
+    fn delete_char(&mut self) {
+        if self.current_buffer().cursor_y >= self.current_buffer().content.len() {
+            return;
+        }
+
+        let at = self.current_buffer().cursor_x.min(self.current_line_len());
+        let y = self.current_buffer().cursor_y;
+
+        if at > 0 {
+            let count = self.deletable_indent().max(1);
+            let text: String = self.current_buffer().content[y]
+                .chars()
+                .skip(at - count)
+                .take(count)
+                .collect();
+            self.edit(EditOp::Delete {
+                x: at - count,
+                y,
+                text,
+            });
+            self.current_buffer_mut().cursor_x = at - count;
+        } else if y > 0 {
+            let x = char_count(&self.current_buffer().content[y - 1]);
+            self.edit(EditOp::JoinLine { x, y: y - 1 });
+
+            let buffer = self.current_buffer_mut();
+            buffer.cursor_y = y - 1;
+            buffer.cursor_x = x;
+        }
+    }
+
+    /// With `expand_tab`, how many of the spaces before the cursor make up one expanded tab,
+    /// so backspace can remove them in one go.
+    fn deletable_indent(&self) -> usize {
+        if !self.expand_tab {
+            return 0;
+        }
+
+        let buffer = self.current_buffer();
+        let line = &buffer.content[buffer.cursor_y];
+        let render_x = cursor_x_to_render_x(line, buffer.cursor_x, self.tab_stop);
+        let previous_stop = render_x.saturating_sub(1) / self.tab_stop * self.tab_stop;
+
+        let before_cursor = &line[..byte_index(line, buffer.cursor_x)];
+        let spaces = before_cursor
+            .chars()
+            .rev()
+            .take_while(|&c| c == ' ')
+            .count();
+
+        spaces.min(render_x - previous_stop)
+    }
+
+    fn delete_char_under_cursor(&mut self) {
+        if self.current_buffer().cursor_y >= self.current_buffer().content.len() {
+            return;
+        }
+
+        let line_len = self.current_line_len();
+        let x = self.current_buffer().cursor_x.min(line_len);
+        let y = self.current_buffer().cursor_y;
+
+        if x < line_len {
+            let text = self.current_buffer().content[y]
+                .chars()
+                .nth(x)
+                .unwrap()
+                .to_string();
+            self.edit(EditOp::Delete { x, y, text });
+        } else if y + 1 < self.current_buffer().content.len() {
+            // at the end of the line, join the next one onto this one
+            self.edit(EditOp::JoinLine { x, y });
+        }
+    }
+
+    /// Applies `op` to the content and records it so it can be undone.
+    fn edit(&mut self, op: EditOp) {
+        self.apply_edit(&op);
+
+        let buffer = self.current_buffer_mut();
+        buffer.redo_stack.clear();
+
+        if buffer.undo_group_open {
+            if let Some(entry) = buffer.undo_stack.back_mut() {
+                // typing in a row extends the last insertion instead of piling up operations
+                if let (
+                    Some(EditOp::Insert { x, y, text }),
+                    EditOp::Insert {
+                        x: new_x,
+                        y: new_y,
+                        text: new_text,
+                    },
+                ) = (entry.ops.last_mut(), &op)
+                {
+                    if y == new_y && *x + char_count(text) == *new_x {
+                        text.push_str(new_text);
+                        return;
+                    }
+                }
+
+                entry.ops.push(op);
+                return;
+            }
+        }
+
+        let entry = UndoEntry {
+            ops: vec![op],
+            cursor: (buffer.cursor_x, buffer.cursor_y),
+        };
+        buffer.undo_group_open = true;
+        self.push_undo_entry(entry);
+    }
+
+    fn push_undo_entry(&mut self, entry: UndoEntry) {
+        let undo_limit = self.undo_limit;
+        let buffer = self.current_buffer_mut();
+
+        buffer.undo_stack.push_back(entry);
+
+        while buffer.undo_stack.len() > undo_limit {
+            buffer.undo_stack.pop_front();
+        }
+    }
+
+    fn apply_edit(&mut self, op: &EditOp) {
+        match op {
+            EditOp::Insert { x, y, text } => {
+                let line = &mut self.current_buffer_mut().content[*y];
+                line.insert_str(byte_index(line, *x), text);
+                self.update_row(*y);
+            }
+            EditOp::Delete { x, y, text } => {
+                let line = &mut self.current_buffer_mut().content[*y];
+                let start = byte_index(line, *x);
+                let end = byte_index(line, *x + char_count(text));
+                line.replace_range(start..end, "");
+                self.update_row(*y);
+            }
+            EditOp::SplitLine { x, y } => {
+                let line = &mut self.current_buffer_mut().content[*y];
+                let tail = line.split_off(byte_index(line, *x));
+                self.update_row(*y);
+                self.insert_row(*y + 1, tail);
+            }
+            EditOp::JoinLine { y, .. } => {
+                let next_line = self.delete_row(*y + 1);
+                self.current_buffer_mut().content[*y].push_str(next_line.as_str());
+                self.update_row(*y);
+            }
+            EditOp::InsertLine { y, line } => self.insert_row(*y, line.clone()),
+            EditOp::RemoveLine { y, .. } => {
+                self.delete_row(*y);
+            }
+        }
+    }
+
+    fn undo(&mut self) {
+        let entry = match self.current_buffer_mut().undo_stack.pop_back() {
+            Some(entry) => entry,
+            None => {
+                self.set_status_message("Nothing to undo");
+                return;
+            }
+        };
+
+        for op in entry.ops.iter().rev() {
+            self.apply_edit(&invert_edit(op));
+        }
+
+        let buffer = self.current_buffer_mut();
+        (buffer.cursor_x, buffer.cursor_y) = entry.cursor;
+        buffer.redo_stack.push(entry);
+    }
+
+    fn redo(&mut self) {
+        let entry = match self.current_buffer_mut().redo_stack.pop() {
+            Some(entry) => entry,
+            None => {
+                self.set_status_message("Nothing to redo");
+                return;
+            }
+        };
+
+        for op in entry.ops.iter() {
+            self.apply_edit(op);
+        }
+
+        if let Some(op) = entry.ops.last() {
+            let buffer = self.current_buffer_mut();
+            (buffer.cursor_x, buffer.cursor_y) = cursor_after_edit(op);
+        }
+        self.push_undo_entry(entry);
+    }
+
+    fn save_file(&mut self) {
+        if self.current_buffer().read_only && !self.current_buffer().file_path.is_empty() {
+            self.set_status_message("Read-only, save it somewhere else with :w <path>");
+            return;
+        }
+        if self.current_buffer().partial {
+            self.set_status_message("Only part of the file was read, save it with :w <path>");
+            return;
+        }
+        if self.current_buffer().file_path.is_empty() {
+            match self.prompt("Save as: {} (ESC to cancel)", None) {
+                Some(file_path) if !self.confirm_overwrite(&file_path) => {
+                    self.set_status_message("Save aborted");
+                    return;
+                }
+                Some(file_path) => {
+                    let buffer = self.current_buffer_mut();
+                    buffer.filename = String::from(extract_filename(&file_path));
+                    buffer.file_path = file_path;
+                    buffer.read_only = false;
+                }
+                None => {
+                    self.set_status_message("Save aborted");
+                    return;
+                }
+            }
+        }
+
+        if !self.create_parent_dir() {
+            return;
+        }
+
+        // the whole file is written back, so the rest of it has to be read first
+        self.load_lines(usize::MAX);
+        if self.trim_trailing_whitespace {
+            self.trim_trailing_whitespace();
+        }
+
+        let ensure_final_newline = self.ensure_final_newline;
+        let make_backup = self.make_backup;
+        let buffer = self.current_buffer_mut();
+
+        // the backup is of the file on disk, which a new file doesn't have yet
+        let mut backup_error = None;
+        if make_backup && !buffer.backed_up && Path::new(&buffer.file_path).exists() {
+            match fs::copy(&buffer.file_path, format!("{}~", buffer.file_path)) {
+                Ok(_) => buffer.backed_up = true,
+                Err(err) => backup_error = Some(err),
+            }
+        }
+
+        let result = write_atomically(&buffer.file_path, |out| {
+            write_text(buffer, ensure_final_newline, out)
+        });
+
+        let mut message = match result {
+            Ok(written) => {
+                buffer.dirty = false;
+                buffer.backed_up = true;
+                buffer.modified = modified_time(&buffer.file_path);
+                buffer.read_from_saved_file(written.stored, written.kept);
+                match written.complete {
+                    true => format!("{} bytes written to disk", written.bytes),
+                    false => format!(
+                        "{} bytes written to disk, with ? for what {} can't hold",
+                        written.bytes,
+                        buffer.encoding.name()
+                    ),
+                }
+            }
+            Err(err) => format!("Can't save! I/O error: {}", err),
+        };
+        if let Some(err) = backup_error {
+            message.push_str(&format!(" (no backup: {})", err));
+        }
+        self.set_status_message(&message);
+    }
+
+    /// Saves the current buffer to `file_path`, which it's saved to from then on.
+    fn save_file_as(&mut self, file_path: &str) {
+        // saving as the file itself is just saving it, which is neither asked about nor
+        // allowed for a read-only file. A copy somewhere else isn't read-only.
+        if position_key(file_path) == position_key(&self.current_buffer().file_path) {
+            self.save_file();
+            return;
+        }
+        if !self.confirm_overwrite(file_path) {
+            self.set_status_message("Save aborted");
+            return;
+        }
+
+        let tab_stop = self.tab_stop;
+        let buffer = self.current_buffer_mut();
+        buffer.read_only = false;
+        buffer.partial = false;
+        buffer.filename = String::from(extract_filename(file_path));
+        buffer.file_path = String::from(file_path);
+        buffer.syntax = detect_syntax(file_path);
+        highlight_all(buffer, tab_stop);
+        // whatever was backed up was the old file
+        buffer.backed_up = false;
+        self.save_file();
+    }
+
+    /// Creates the directory the current buffer is saved in if it isn't there, asking first
+    /// unless `mkdir_on_save` is set. Returns whether the file can be saved.
+    fn create_parent_dir(&mut self) -> bool {
+        let file_path = PathBuf::from(&self.current_buffer().file_path);
+        let Some(dir) = file_path.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+            return true;
+        };
+        if dir.exists() {
+            return true;
+        }
+
+        if !self.mkdir_on_save {
+            let answer = self.prompt("Create directory? (y/n) {}", None);
+            if !answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y")) {
+                self.set_status_message("Save aborted");
+                return false;
+            }
+        }
+
+        match fs::create_dir_all(dir) {
+            Ok(()) => true,
+            Err(err) => {
+                let message = format!("Can't create {}: {}", dir.display(), err);
+                self.set_status_message(&message);
+                false
+            }
+        }
+    }
+
+    /// Asks before saving over `file_path`, another file that's there already. Returns
+    /// whether to go ahead.
+    fn confirm_overwrite(&mut self, file_path: &str) -> bool {
+        if !Path::new(file_path).exists() {
+            return true;
+        }
+
+        let answer = self.prompt("Overwrite existing file? (y/n) {}", None);
+        answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y"))
+    }
+
+    /// Removes the spaces and tabs at the end of every line, as one undo step.
+    fn trim_trailing_whitespace(&mut self) {
+        self.current_buffer_mut().undo_group_open = false;
+
+        for y in 0..self.current_buffer().content.len() {
+            let line = &self.current_buffer().content.peek(y).unwrap();
+            let x = char_count(line.trim_end_matches([' ', '\t']));
+            let text: String = line.chars().skip(x).collect();
+            if !text.is_empty() {
+                self.edit(EditOp::Delete { x, y, text });
+            }
+        }
+        self.current_buffer_mut().undo_group_open = false;
+
+        // the cursor may have been out in the whitespace
+        let line_len = self.current_line_len();
+        let buffer = self.current_buffer_mut();
+        buffer.cursor_x = buffer.cursor_x.min(line_len);
+    }
+
+    /// Reads a line of input in the message bar, showing `prompt_text` with the input in place
+    /// of `{}`. Returns `None` if the user cancels with Escape or submits nothing.
+    fn prompt(
+        &mut self,
+        prompt_text: &str,
+        mut callback: Option<PromptCallback>,
+    ) -> Option<String> {
+        let mut input = String::new();
+        let mut prompt_text = String::from(prompt_text);
+
+        loop {
+            self.set_status_message(&prompt_text.replace("{}", &input));
+            update_window_size(self);
+            self.scroll();
+            refresh_screen(self);
+
+            let key = match read_key(&mut self.input) {
+                CTRL_C => ESCAPE, // cancels the prompt the same way
+                key => key,
+            };
+            let result = match key {
+                ENTER if !input.is_empty() => Some(Some(input.clone())),
+                ENTER | ESCAPE => Some(None),
+                BACKSPACE => {
+                    input.pop();
+                    None
+                }
+                _ => {
+                    if let Some(c) = printable_char(key) {
+                        input.push(c);
+                    }
+                    None
+                }
+            };
+
+            // the keys that only wake the loop up, to redraw, aren't the user's
+            if let Some(callback) = callback
+                .as_mut()
+                .filter(|_| !matches!(key, IDLE | WINDOW_RESIZED))
+            {
+                if let Some(text) = callback(self, &mut input, key) {
+                    prompt_text = text;
+                }
+            }
+
+            if let Some(result) = result {
+                self.set_status_message("");
+                return result;
+            }
+        }
+    }
+
+    fn goto_line(&mut self) {
+        let input = match self.prompt("Go to line: {}", None) {
+            Some(input) => input,
+            None => return,
+        };
+
+        let line: usize = match input.trim().parse() {
+            Ok(line) => line,
+            Err(_) => {
+                self.set_status_message(&format!("Not a line number: {}", input));
+                return;
+            }
+        };
+
+        self.load_lines(line);
+        let buffer = self.current_buffer_mut();
+        buffer.cursor_y = line
+            .saturating_sub(1)
+            .min(buffer.content.len().saturating_sub(1));
+        buffer.cursor_x = 0;
+    }
+
+    fn find(&mut self) {
+        self.load_lines(usize::MAX);
+        let buffer = self.current_buffer();
+        let saved_cursor = (buffer.cursor_x, buffer.cursor_y);
+        let saved_offset = (buffer.offset_x, buffer.offset_y);
+
+        let mut last_match: Option<(usize, usize)> = None;
+
+        // the options are kept from the last search, the query isn't
+        self.search.set_query("");
+        self.searching = true;
+
+        let mut on_key = |editor: &mut Editor, query: &mut String, key: u32| {
+            match key {
+                CTRL_R => editor.search.regex = !editor.search.regex,
+                CTRL_A => editor.search.ignore_case = !editor.search.ignore_case,
+                CTRL_W => editor.search.whole_word = !editor.search.whole_word,
+                _ => {}
+            }
+            if matches!(key, CTRL_R | CTRL_A | CTRL_W) || *query != editor.search.query {
+                editor.search.set_query(query);
+            }
+
+            let search = &editor.search;
+            let found = match key {
+                ENTER => return None,
+                ESCAPE => {
+                    let buffer = editor.current_buffer_mut();
+                    (buffer.cursor_x, buffer.cursor_y) = saved_cursor;
+                    (buffer.offset_x, buffer.offset_y) = saved_offset;
+                    return None;
+                }
+                ARROW_RIGHT | ARROW_DOWN => match last_match {
+                    Some((x, y)) => editor.find_next(search, x + 1, y),
+                    None => editor.find_next(search, saved_cursor.0, saved_cursor.1),
+                },
+                ARROW_LEFT | ARROW_UP => match last_match {
+                    Some((x, y)) => editor.find_previous(search, x, y),
+                    None => editor.find_previous(search, saved_cursor.0, saved_cursor.1),
+                },
+                // the query or how it's matched changed, so look again from where the search
+                // started
+                BACKSPACE | CTRL_R | CTRL_A | CTRL_W => {
+                    editor.find_next(search, saved_cursor.0, saved_cursor.1)
+                }
+                _ if printable_char(key).is_some() => {
+                    editor.find_next(search, saved_cursor.0, saved_cursor.1)
+                }
+                _ => return None,
+            };
+
+            if let Some((x, y)) = found {
+                let buffer = editor.current_buffer_mut();
+                buffer.cursor_x = x;
+                buffer.cursor_y = y;
+                last_match = found;
+            }
+
+            Some(search_prompt(&editor.search))
+        };
+
+        let prompt_text = search_prompt(&self.search);
+        self.prompt(&prompt_text, Some(&mut on_key));
+        self.searching = false;
+    }
+
+    /// Replaces matches of a search string from the cursor to the end of the file, asking
+    /// about each one unless the user says to replace them all.
+    fn replace(&mut self) {
+        let Some(query) = self.prompt("Replace: {} (ESC to cancel)", None) else {
+            return;
+        };
+        let Some(replacement) = self.prompt("Replace with: {} (ESC to cancel)", None) else {
+            return;
+        };
+        self.load_lines(usize::MAX);
+
+        let buffer = self.current_buffer();
+        let (mut x, mut y) = match buffer.cursor_y < buffer.content.len() {
+            true => (buffer.cursor_x, buffer.cursor_y),
+            false => (0, 0),
+        };
+        let search = Search::new(&query);
+        let mut replace_all = false;
+        let mut count = 0;
+        let started = Instant::now();
+
+        while let Some((found_x, found_y)) = self.find_next(&search, x, y) {
+            // find_next wraps around, so a match behind us means the end of the file was passed
+            if (found_y, found_x) < (y, x) {
+                break;
+            }
+
+            if replace_all {
+                let lines = self.current_buffer().content.len() as u64;
+                let progress = format!("Replacing... {}%", percent(found_y as u64, lines));
+                self.show_progress(started, &progress);
+            } else {
+                let buffer = self.current_buffer_mut();
+                (buffer.cursor_x, buffer.cursor_y) = (found_x, found_y);
+                self.set_status_message("Replace? (y/n/a/q)");
+                update_window_size(self);
+                self.scroll();
+                refresh_screen(self);
+
+                let key = read_key(&mut self.input);
+                match printable_char(key).map(|c| c.to_ascii_lowercase()) {
+                    Some('y') => {}
+                    Some('a') => replace_all = true,
+                    Some('n') => {
+                        // one character on, so a match overlapping this one is still offered
+                        (x, y) = (found_x + 1, found_y);
+                        continue;
+                    }
+                    Some('q') => break,
+                    _ if key == ESCAPE || key == CTRL_C => break,
+                    _ => continue,
+                }
+            }
+
+            self.edit(EditOp::Delete {
+                x: found_x,
+                y: found_y,
+                text: query.clone(),
+            });
+            if !replacement.is_empty() {
+                self.edit(EditOp::Insert {
+                    x: found_x,
+                    y: found_y,
+                    text: replacement.clone(),
+                });
+            }
+            count += 1;
+
+            // carry on after the replacement, so it isn't matched again
+            (x, y) = (found_x + char_count(&replacement), found_y);
+            let buffer = self.current_buffer_mut();
+            (buffer.cursor_x, buffer.cursor_y) = (x, y);
+        }
+
+        self.set_status_message(&format!("Replaced {} of \"{}\"", count, query));
+    }
+
+    fn find_next(&self, search: &Search, from_x: usize, from_y: usize) -> Option<(usize, usize)> {
+        let content = &self.current_buffer().content;
+        if search.query.is_empty() || content.is_empty() {
+            return None;
+        }
+
+        let total = content.len();
+        let (from_x, from_y) = if from_y < total {
+            (from_x, from_y)
+        } else {
+            (0, 0)
+        };
+
+        // the line the search starts on is visited twice: first from the cursor onwards and
+        // then, after wrapping around, from its beginning
+        for i in 0..=total {
+            let y = (from_y + i) % total;
+            // going through the whole file mustn't keep all of it in memory
+            let line = &content.peek(y).unwrap();
+
+            let start = if i == 0 { byte_index(line, from_x) } else { 0 };
+            if i == total && from_x == 0 {
+                break;
+            }
+
+            if let Some((x, _)) = search.find_from(line, start) {
+                return Some((char_count(&line[..x]), y));
+            }
+        }
+
+        None
+    }
+
+    fn find_previous(
+        &self,
+        search: &Search,
+        from_x: usize,
+        from_y: usize,
+    ) -> Option<(usize, usize)> {
+        let content = &self.current_buffer().content;
+        if search.query.is_empty() || content.is_empty() {
+            return None;
+        }
+
+        let total = content.len();
+        let (from_x, from_y) = if from_y < total {
+            (from_x, from_y)
+        } else {
+            (0, 0)
+        };
+
+        for i in 0..=total {
+            let y = (from_y + total - i % total) % total;
+            let line = &content.peek(y).unwrap();
+
+            // on the line the search starts on, only matches beginning before the cursor count
+            let end = if i == 0 {
+                byte_index(line, from_x)
+            } else {
+                line.len() + 1
+            };
+
+            if let Some((x, _)) = search.matches(line).filter(|&(x, _)| x < end).last() {
+                return Some((char_count(&line[..x]), y));
+            }
+        }
+
+        None
+    }
+
+    pub fn die(&mut self, err: io::Error) -> ! {
+        self.restore_terminal();
+
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+
+    fn exit(&mut self) {
+        self.save_positions();
+
+        // process::exit doesn't run destructors, so the terminal is restored by hand
+        self.restore_terminal();
+
+        // piped in text that wasn't saved anywhere goes on down the pipe
+        if let Some(mut output) = self.piped_output.take() {
+            for buffer in &self.buffers {
+                if buffer.from_stdin && buffer.file_path.is_empty() {
+                    let _ = write_text(buffer, self.ensure_final_newline, &mut output);
+                }
+            }
+        }
+
+        process::exit(0);
+    }
+
+    /// Leaves raw mode, with the cursor back to the terminal's own shape if it was changed.
+    fn restore_terminal(&mut self) {
+        if self.cursor_shape && self.raw_mode.is_some() {
+            write(b"\x1b[0 q");
+        }
+        self.raw_mode = None;
+    }
+
+    fn jump_to_matching_bracket(&mut self) {
+        let buffer = self.current_buffer_mut();
+        match buffer.matching_bracket(usize::MAX) {
+            Some((x, y)) => (buffer.cursor_x, buffer.cursor_y) = (x, y),
+            None => self.set_status_message("No matching bracket"),
+        }
+    }
+
+    /// Moves the cursor like the arrow `key`, starting a selection where it was if there
+    /// isn't one.
+    fn extend_selection(&mut self, key: u32) {
+        let buffer = self.current_buffer_mut();
+        if buffer.selection_anchor.is_none() {
+            buffer.selection_anchor = Some((buffer.cursor_x, buffer.cursor_y));
+        }
+
+        self.move_cursor(key);
+    }
+
+    /// The first and last line of a selection that takes in a line break, so whole lines are
+    /// selected. A selection ending at the start of a line leaves that line out, so a single
+    /// line selected with Shift-Down from its start is just that line.
+    fn selected_lines(&self) -> Option<(usize, usize)> {
+        let ((_, start_y), (end_x, end_y)) = self.current_buffer().selection()?;
+        let last = self.current_buffer().content.len().checked_sub(1)?;
+        if start_y == end_y {
+            return None;
+        }
+
+        let last_selected = match end_x {
+            0 => end_y - 1,
+            _ => end_y,
+        }
+        .min(last);
+
+        (start_y <= last_selected).then_some((start_y, last_selected))
+    }
+
+    /// Adds a level of indentation to the selected lines, or with `indent` false takes one
+    /// away from them or from the cursor's line. Lines without any are left alone.
+    fn indent_lines(&mut self, indent: bool) {
+        let (first, last) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => {
+                let y = self.current_buffer().cursor_y;
+                if y >= self.current_buffer().content.len() {
+                    return;
+                }
+                (y, y)
+            }
+        };
+
+        let unit = match self.expand_tab {
+            true => " ".repeat(self.tab_stop),
+            false => String::from("\t"),
+        };
+
+        self.current_buffer_mut().undo_group_open = false;
+
+        for y in first..=last {
+            let line = &self.current_buffer().content[y];
+            let shift = if indent {
+                if line.is_empty() {
+                    continue;
+                }
+                self.edit(EditOp::Insert {
+                    x: 0,
+                    y,
+                    text: unit.clone(),
+                });
+                char_count(&unit) as isize
+            } else {
+                let text: String = match line.starts_with('\t') {
+                    true => String::from("\t"),
+                    false => line
+                        .chars()
+                        .take(self.tab_stop)
+                        .take_while(|&c| c == ' ')
+                        .collect(),
+                };
+                if text.is_empty() {
+                    continue;
+                }
+                let removed = char_count(&text) as isize;
+                self.edit(EditOp::Delete { x: 0, y, text });
+                -removed
+            };
+
+            self.move_marks(y, 0, shift);
+        }
+
+        self.current_buffer_mut().undo_group_open = false;
+    }
+
+    /// Puts a copy of the selected lines, or of the cursor's line, right below them, as one
+    /// undo step.
+    fn duplicate_lines(&mut self) {
+        let (first, last) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => {
+                let y = self.current_buffer().cursor_y;
+                if y >= self.current_buffer().content.len() {
+                    return;
+                }
+                (y, y)
+            }
+        };
+
+        self.current_buffer_mut().undo_group_open = false;
+        for y in first..=last {
+            let line = self.current_buffer().content[y].clone();
+            self.edit(EditOp::InsertLine {
+                y: y + last - first + 1,
+                line,
+            });
+        }
+        self.current_buffer_mut().undo_group_open = false;
+
+        // the selection goes along with the cursor, so it's the copy that's selected
+        if self.duplicate_to_copy {
+            let count = last - first + 1;
+            let buffer = self.current_buffer_mut();
+            buffer.cursor_y += count;
+            if let Some((_, anchor_y)) = buffer.selection_anchor.as_mut() {
+                *anchor_y += count;
+            }
+        }
+    }
+
+    /// Swaps the selected lines, or the cursor's line, with the line above them, or with
+    /// `up` false the line below, as one undo step. The cursor and selection move with them.
+    fn move_lines(&mut self, up: bool) {
+        let (first, last) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => {
+                let y = self.current_buffer().cursor_y;
+                if y >= self.current_buffer().content.len() {
+                    return;
+                }
+                (y, y)
+            }
+        };
+        if (up && first == 0) || (!up && last + 1 >= self.current_buffer().content.len()) {
+            return;
+        }
+
+        // the line on the other side is taken out and put back on this side
+        let (from, to) = match up {
+            true => (first - 1, last),
+            false => (last + 1, first),
+        };
+
+        self.current_buffer_mut().undo_group_open = false;
+        let line = self.current_buffer().content[from].clone();
+        self.edit(EditOp::RemoveLine {
+            y: from,
+            line: line.clone(),
+        });
+        self.edit(EditOp::InsertLine { y: to, line });
+        self.current_buffer_mut().undo_group_open = false;
+
+        let buffer = self.current_buffer_mut();
+        let shift = |y: &mut usize| match up {
+            true => *y -= 1,
+            false => *y += 1,
+        };
+        shift(&mut buffer.cursor_y);
+        if let Some((_, anchor_y)) = buffer.selection_anchor.as_mut() {
+            shift(anchor_y);
+        }
+    }
+
+    /// Rewraps the cursor's paragraph, the lines around it up to a blank line, so its words
+    /// fill lines up to `fill_column`, as one undo step. An indentation all its lines share is
+    /// kept.
+    fn rewrap_paragraph(&mut self) {
+        let content = &self.current_buffer().content;
+        let y = self.current_buffer().cursor_y;
+        if content.get(y).is_none_or(|line| line.trim().is_empty()) {
+            return;
+        }
+
+        let mut first = y;
+        while first > 0 && !content[first - 1].trim().is_empty() {
+            first -= 1;
+        }
+        let mut last = y;
+        while last + 1 < content.len() && !content[last + 1].trim().is_empty() {
+            last += 1;
+        }
+
+        let lines: Vec<String> = (first..=last).map(|y| content[y].clone()).collect();
+        let indent: String = lines[0].chars().take_while(|c| c.is_whitespace()).collect();
+        let indent = match lines.iter().all(|line| {
+            line.strip_prefix(indent.as_str())
+                .is_some_and(|rest| !rest.starts_with(char::is_whitespace))
+        }) {
+            true => indent,
+            false => String::new(),
+        };
+
+        // a word longer than the line is left on one of its own
+        let width = |line: &str| cursor_x_to_render_x(line, char_count(line), self.tab_stop);
+        let mut wrapped = Vec::new();
+        let mut line = indent.clone();
+        for word in lines.iter().flat_map(|line| line.split_whitespace()) {
+            if line.len() > indent.len() && width(&line) + 1 + width(word) > self.fill_column {
+                wrapped.push(line);
+                line = indent.clone();
+            }
+            if line.len() > indent.len() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        wrapped.push(line);
+
+        if wrapped == lines {
+            return;
+        }
+
+        self.current_buffer_mut().undo_group_open = false;
+        for y in (first..=last).rev() {
+            let line = self.current_buffer().content[y].clone();
+            self.edit(EditOp::RemoveLine { y, line });
+        }
+        let y = first + wrapped.len() - 1;
+        let x = char_count(&wrapped[wrapped.len() - 1]);
+        for (i, line) in wrapped.into_iter().enumerate() {
+            self.edit(EditOp::InsertLine { y: first + i, line });
+        }
+        self.current_buffer_mut().undo_group_open = false;
+
+        // the cursor goes to the end of the paragraph, ready for the next one
+        let buffer = self.current_buffer_mut();
+        (buffer.cursor_x, buffer.cursor_y) = (x, y);
+    }
+
+    /// Runs the text through the formatter for the kind of file it is and puts what comes out
+    /// in its place, as one undo step. Only the lines that changed are replaced, so the
+    /// cursor stays near where it was. If the formatter fails, what it said is shown instead.
+    fn format_buffer(&mut self) {
+        let extension = Path::new(&self.current_buffer().file_path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let Some(command) = self.formatters.get(extension) else {
+            self.set_status_message("No formatter for this kind of file");
+            return;
+        };
+        let mut words = command.split_whitespace();
+        let Some(program) = words.next() else {
+            return;
+        };
+        let (program, args): (String, Vec<String>) =
+            (String::from(program), words.map(String::from).collect());
+
+        self.load_lines(usize::MAX);
+        let mut text = self.current_buffer().content.join("\n");
+        text.push('\n');
+
+        let child = Command::new(&program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                self.set_status_message(&format!("Can't run {}: {}", program, err));
+                return;
+            }
+        };
+
+        // the text is written from another thread, so a formatter that writes before it's
+        // read everything doesn't leave the two waiting on each other
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || stdin.write_all(text.as_bytes()));
+        let output = child.wait_with_output();
+        let _ = writer.join();
+
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                self.set_status_message(&format!("Can't run {}: {}", program, err));
+                return;
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = match stderr.split_whitespace().collect::<Vec<_>>().join(" ") {
+                error if error.is_empty() => format!("{} failed: {}", program, output.status),
+                error => format!("{}: {}", program, error),
+            };
+            self.set_status_message(&message);
+            return;
+        }
+        let Ok(formatted) = String::from_utf8(output.stdout) else {
+            self.set_status_message(&format!("{} wrote something that isn't UTF-8", program));
+            return;
+        };
+
+        let formatted: Vec<&str> = formatted.lines().collect();
+        let content = &self.current_buffer().content;
+        let len = content.len();
+        let same_start = (0..len.min(formatted.len()))
+            .take_while(|&y| content[y] == formatted[y])
+            .count();
+        let same_end = (0..len.min(formatted.len()) - same_start)
+            .take_while(|&i| content[len - 1 - i] == formatted[formatted.len() - 1 - i])
+            .count();
+        if same_start == len && len == formatted.len() {
+            self.set_status_message("Already formatted");
+            return;
+        }
+
+        self.current_buffer_mut().undo_group_open = false;
+        for y in (same_start..len - same_end).rev() {
+            let line = self.current_buffer().content[y].clone();
+            self.edit(EditOp::RemoveLine { y, line });
+        }
+        for (y, line) in formatted
+            .iter()
+            .enumerate()
+            .take(formatted.len() - same_end)
+            .skip(same_start)
+        {
+            self.edit(EditOp::InsertLine {
+                y,
+                line: String::from(*line),
+            });
+        }
+        self.current_buffer_mut().undo_group_open = false;
+
+        // the lines after the change keep the cursor on the same text, the ones in it as
+        // close as there is
+        let buffer = self.current_buffer_mut();
+        if buffer.cursor_y >= len - same_end {
+            buffer.cursor_y = buffer.cursor_y + formatted.len() - len;
+        }
+        buffer.cursor_y = buffer.cursor_y.min(buffer.content.len().saturating_sub(1));
+        self.snap_cursor_to_line();
+        self.set_status_message(&format!("Formatted with {}", program));
+    }
+
+    /// Says how many lines, words and characters there are in the selection or the buffer.
+    fn show_stats(&mut self) {
+        let selected = self.current_buffer().selection().is_some();
+        if !selected {
+            self.load_lines(usize::MAX);
+        }
+
+        let (lines, words, chars) = buffer_stats(self);
+        let message = format!(
+            "{}{} lines, {} words, {} characters",
+            if selected { "Selected: " } else { "" },
+            lines,
+            words,
+            chars
+        );
+        self.set_status_message(&message);
+    }
+
+    /// Comments out the selected lines, or the cursor's line, after their indentation. If
+    /// they're all comments already, they're uncommented instead. Blank lines are skipped.
+    fn toggle_comment(&mut self) {
+        let (first, last) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => {
+                let y = self.current_buffer().cursor_y;
+                if y >= self.current_buffer().content.len() {
+                    return;
+                }
+                (y, y)
+            }
+        };
+
+        let prefix = line_comment(&self.current_buffer().file_path);
+        let content = &self.current_buffer().content;
+        let commented = (first..=last)
+            .map(|y| content[y].trim_start())
+            .filter(|line| !line.is_empty())
+            .all(|line| line.starts_with(prefix));
+
+        self.current_buffer_mut().undo_group_open = false;
+
+        for y in first..=last {
+            let line = &self.current_buffer().content[y];
+            let rest = line.trim_start();
+            if rest.is_empty() {
+                continue;
+            }
+            let x = char_count(line) - char_count(rest);
+
+            if commented {
+                let after = &rest[prefix.len()..];
+                let text = match after.starts_with(' ') {
+                    true => format!("{} ", prefix),
+                    false => String::from(prefix),
+                };
+                let removed = char_count(&text) as isize;
+                self.edit(EditOp::Delete { x, y, text });
+                self.move_marks(y, x, -removed);
+            } else {
+                let text = format!("{} ", prefix);
+                let added = char_count(&text) as isize;
+                self.edit(EditOp::Insert { x, y, text });
+                self.move_marks(y, x, added);
+            }
+        }
+
+        self.current_buffer_mut().undo_group_open = false;
+    }
+
+    /// Moves the cursor and selection anchor along with the text on line `y` after `at`, when
+    /// it's shifted by `shift` characters. Whatever is at `at` itself stays, so a selection
+    /// starting at the beginning of a line keeps all of it.
+    fn move_marks(&mut self, y: usize, at: usize, shift: isize) {
+        let move_x = |x: usize| match x > at {
+            true => x.saturating_add_signed(shift).max(at),
+            false => x,
+        };
+
+        let buffer = self.current_buffer_mut();
+        if buffer.cursor_y == y {
+            buffer.cursor_x = move_x(buffer.cursor_x);
+        }
+        if let Some((x, anchor_y)) = buffer.selection_anchor {
+            if anchor_y == y {
+                buffer.selection_anchor = Some((move_x(x), y));
+            }
+        }
+    }
+
+    /// Deletes the selected text, leaving the cursor where it started. Returns whether there
+    /// was anything selected.
+    fn delete_selection(&mut self) -> bool {
+        let buffer = self.current_buffer_mut();
+        let Some(((start_x, start_y), (end_x, end_y))) = buffer.selection() else {
+            return false;
+        };
+        buffer.selection_anchor = None;
+        buffer.undo_group_open = false;
+
+        let len = buffer.content.len();
+        if start_y >= len {
+            return true;
+        }
+
+        // the row past the last line has nothing on it, so the selection ends with the file
+        let (end_x, end_y) = match end_y < len {
+            true => (end_x.min(char_count(&buffer.content[end_y])), end_y),
+            false => (char_count(&buffer.content[len - 1]), len - 1),
+        };
+        let start_x = start_x.min(char_count(&buffer.content[start_y]));
+
+        if start_y == end_y {
+            if end_x > start_x {
+                let text = buffer.content[start_y]
+                    .chars()
+                    .skip(start_x)
+                    .take(end_x - start_x)
+                    .collect();
+                self.edit(EditOp::Delete {
+                    x: start_x,
+                    y: start_y,
+                    text,
+                });
+            }
+        } else {
+            if end_x > 0 {
+                let text = buffer.content[end_y].chars().take(end_x).collect();
+                self.edit(EditOp::Delete {
+                    x: 0,
+                    y: end_y,
+                    text,
+                });
+            }
+            for y in (start_y + 1..end_y).rev() {
+                let line = self.current_buffer().content[y].clone();
+                self.edit(EditOp::RemoveLine { y, line });
+            }
+            let text: String = self.current_buffer().content[start_y]
+                .chars()
+                .skip(start_x)
+                .collect();
+            if !text.is_empty() {
+                self.edit(EditOp::Delete {
+                    x: start_x,
+                    y: start_y,
+                    text,
+                });
+            }
+            self.edit(EditOp::JoinLine {
+                x: start_x,
+                y: start_y,
+            });
+        }
+
+        let buffer = self.current_buffer_mut();
+        (buffer.cursor_x, buffer.cursor_y) = (start_x, start_y);
+        true
+    }
+
+    fn move_cursor(&mut self, key: u32) {
+        if self.wrap && matches!(key, ARROW_UP | ARROW_DOWN) {
+            self.move_by_screen_row(key == ARROW_DOWN);
+            return;
+        }
+        if key == CTRL_END {
+            self.load_lines(usize::MAX);
+        }
+
+        let rows = self.rows;
+        let line_len = self.current_line_len();
+        let buffer = self.current_buffer_mut();
+        let indent = buffer.content.get(buffer.cursor_y).map_or(0, |line| {
+            line.chars().take_while(|c| c.is_whitespace()).count()
+        });
+        let goal_x = match buffer.goal_x {
+            Some((goal_x, at)) if at == (buffer.cursor_x, buffer.cursor_y) => goal_x,
+            _ => buffer.cursor_x,
+        };
+
+        match key {
+            ARROW_UP if buffer.cursor_y > 0 => buffer.cursor_y -= 1,
+            ARROW_DOWN if buffer.cursor_y + 1 < buffer.content.len() => buffer.cursor_y += 1,
+            ARROW_LEFT if buffer.cursor_x > 0 => buffer.cursor_x -= 1,
+            ARROW_RIGHT if buffer.cursor_x < line_len => buffer.cursor_x += 1,
+            // the start of the text on the line first, and the very start when already there
+            HOME if buffer.cursor_x != indent => buffer.cursor_x = indent,
+            HOME => buffer.cursor_x = 0,
+            END => buffer.cursor_x = line_len,
+            PAGE_UP => {
+                // go to the top of the screen first, then a whole screen up
+                buffer.cursor_y = buffer.offset_y.saturating_sub(rows);
+            }
+            PAGE_DOWN => {
+                // go to the bottom of the screen first, then a whole screen down
+                let bottom = buffer.offset_y + rows - 1;
+                buffer.cursor_y = (bottom + rows).min(buffer.content.len().saturating_sub(1));
+            }
+            CTRL_HOME => (buffer.cursor_x, buffer.cursor_y) = (0, 0),
+            CTRL_END => {
+                buffer.cursor_y = buffer.content.len().saturating_sub(1);
+                buffer.cursor_x = buffer
+                    .content
+                    .get(buffer.cursor_y)
+                    .map_or(0, |line| char_count(line));
+            }
+            CTRL_ARROW_RIGHT => buffer.move_to_next_word(),
+            CTRL_ARROW_LEFT => buffer.move_to_previous_word(),
+            _ => {}
+        };
+
+        if matches!(key, ARROW_UP | ARROW_DOWN | PAGE_UP | PAGE_DOWN) {
+            self.current_buffer_mut().cursor_x = goal_x;
+            self.snap_cursor_to_line();
+            let buffer = self.current_buffer_mut();
+            buffer.goal_x = Some((goal_x, (buffer.cursor_x, buffer.cursor_y)));
+        }
+    }
+
+    fn snap_cursor_to_line(&mut self) {
+        let line_len = self.current_line_len();
+        let buffer = self.current_buffer_mut();
+
+        if buffer.cursor_x > line_len {
+            buffer.cursor_x = line_len;
+        }
+    }
+
+    fn current_line_len(&self) -> usize {
+        let buffer = self.current_buffer();
+
+        // the row past the end of the file is always empty
+        buffer
+            .content
+            .get(buffer.cursor_y)
+            .map_or(0, |line| char_count(line))
+    }
+}
+
+impl Buffer {
+    /// Whether the whole file has been read in, so `content` has all of its lines.
+    pub fn is_loaded(&self) -> bool {
+        self.unread.is_none()
+    }
+
+    /// Forgets the lines far from the screen and the cursor that are as they were read, to be
+    /// read from the file again when they're needed. Changed lines stay in memory.
+    pub fn forget_far_lines(&mut self, rows: usize) {
+        // lines read back from a file written over since wouldn't be the ones forgotten
+        if self.opened.is_none() {
+            return;
+        }
+        let around = |y: usize, rows: usize| y.saturating_sub(KEPT_LINES)..y + rows + KEPT_LINES;
+        let keep = [around(self.offset_y, rows), around(self.cursor_y, 1)];
+        self.content.forget(&keep);
+    }
+
+    /// Opens the file at `path` to read forgotten lines back from, where they end with
+    /// `ending`, or with either one as the file is first read.
+    fn open_lines(&mut self, path: &str, ending: Option<LineEnding>) -> Option<Source<String>> {
+        self.opened = File::open(path).ok().and_then(|file| {
+            let metadata = file.metadata().ok()?;
+            Some((file, metadata.modified().ok(), metadata.len()))
+        });
+        let file = self.opened.as_ref()?.0.try_clone().ok()?;
+        Some(line_source(file, self.encoding, ending))
+    }
+
+    /// Reads the lines not in memory from the file just saved from now on, where `stored` says
+    /// each chunk of them went, keeping the `kept` chunks that can't be read back from it.
+    fn read_from_saved_file(
+        &mut self,
+        stored: Vec<Option<Range<u64>>>,
+        kept: Vec<(usize, Vec<String>)>,
+    ) {
+        for (chunk, lines) in kept {
+            self.content.keep(chunk, lines);
+        }
+        let path = self.file_path.clone();
+        let source = self.open_lines(&path, Some(self.line_ending));
+        self.content.store(stored, source);
+    }
+
+    /// Where the bracket matching the one under the cursor is, looking across at most
+    /// `max_lines` lines. Brackets in between are counted, so nested pairs are skipped over.
+    pub fn matching_bracket(&self, max_lines: usize) -> Option<(usize, usize)> {
+        let line: Vec<char> = self.content.get(self.cursor_y)?.chars().collect();
+        let bracket = *line.get(self.cursor_x)?;
+
+        let (open, close, forward) = match bracket {
+            '(' => ('(', ')', true),
+            '[' => ('[', ']', true),
+            '{' => ('{', '}', true),
+            ')' => ('(', ')', false),
+            ']' => ('[', ']', false),
+            '}' => ('{', '}', false),
+            _ => return None,
+        };
+
+        let mut depth = 0;
+        let mut y = self.cursor_y;
+        let mut chars = line;
+        let mut x = self.cursor_x;
+
+        for _ in 0..max_lines {
+            let positions: Vec<usize> = match forward {
+                true => (x..chars.len()).collect(),
+                false => (0..chars.len().min(x.saturating_add(1))).rev().collect(),
+            };
+
+            for i in positions {
+                if chars[i] == open {
+                    depth += if forward { 1 } else { -1 };
+                } else if chars[i] == close {
+                    depth += if forward { -1 } else { 1 };
+                }
+                if depth == 0 {
+                    return Some((i, y));
+                }
+            }
+
+            if forward && y + 1 < self.content.len() {
+                y += 1;
+                x = 0;
+            } else if !forward && y > 0 {
+                y -= 1;
+                x = usize::MAX;
+            } else {
+                return None;
+            }
+            chars = self.content[y].chars().collect();
+        }
+
+        None
+    }
+
+    /// The start and end of the selected text as `(x, y)`, the earlier one first, or `None`
+    /// if nothing is selected.
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.selection_anchor?;
+        let cursor = (self.cursor_x, self.cursor_y);
+
+        match (anchor.1, anchor.0).cmp(&(cursor.1, cursor.0)) {
+            Ordering::Less => Some((anchor, cursor)),
+            Ordering::Greater => Some((cursor, anchor)),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// The word the cursor is on, if it's on a word character.
+    pub fn word_at_cursor(&self) -> Option<String> {
+        let chars: Vec<char> = self.content.get(self.cursor_y)?.chars().collect();
+        if !is_word_char(*chars.get(self.cursor_x)?) {
+            return None;
+        }
+
+        let start = chars[..self.cursor_x]
+            .iter()
+            .rposition(|&c| !is_word_char(c))
+            .map_or(0, |i| i + 1);
+        let end = chars[self.cursor_x..]
+            .iter()
+            .position(|&c| !is_word_char(c))
+            .map_or(chars.len(), |i| self.cursor_x + i);
+
+        Some(chars[start..end].iter().collect())
+    }
+
+    /// Moves right to where the current run of word (or non-word) characters ends, going on to
+    /// the start of the next line when already at the end of one.
+    fn move_to_next_word(&mut self) {
+        let chars: Vec<char> = match self.content.get(self.cursor_y) {
+            Some(line) => line.chars().collect(),
+            None => return,
+        };
+
+        let mut x = self.cursor_x;
+
+        if x >= chars.len() {
+            if self.cursor_y + 1 < self.content.len() {
+                self.cursor_y += 1;
+                self.cursor_x = 0;
+            }
+            return;
+        }
+
+        let word = is_word_char(chars[x]);
+        while x < chars.len() && is_word_char(chars[x]) == word {
+            x += 1;
+        }
+
+        self.cursor_x = x;
+    }
+
+    /// Moves left to where the run of word (or non-word) characters before the cursor starts,
+    /// going back to the end of the previous line when already at the start of one.
+    fn move_to_previous_word(&mut self) {
+        if self.cursor_x == 0 {
+            if self.cursor_y > 0 {
+                self.cursor_y -= 1;
+                self.cursor_x = char_count(&self.content[self.cursor_y]);
+            }
+            return;
+        }
+
+        let chars: Vec<char> = match self.content.get(self.cursor_y) {
+            Some(line) => line.chars().collect(),
+            None => return,
+        };
+
+        let mut x = self.cursor_x.min(chars.len());
+
+        let word = is_word_char(chars[x - 1]);
+        while x > 0 && is_word_char(chars[x - 1]) == word {
+            x -= 1;
+        }
+
+        self.cursor_x = x;
+    }
+}
+
+fn extract_filename(file_path: &str) -> &str {
+    let mut last_slash_index = 0;
+
+    for (i, &char) in file_path.as_bytes().iter().enumerate() {
+        if char == b'/' {
+            last_slash_index = i + 1;
+        }
+    }
+
+    &file_path[last_slash_index..]
+}
+
+fn invert_edit(op: &EditOp) -> EditOp {
+    match op {
+        EditOp::Insert { x, y, text } => EditOp::Delete {
+            x: *x,
+            y: *y,
+            text: text.clone(),
+        },
+        EditOp::Delete { x, y, text } => EditOp::Insert {
+            x: *x,
+            y: *y,
+            text: text.clone(),
+        },
+        EditOp::SplitLine { x, y } => EditOp::JoinLine { x: *x, y: *y },
+        EditOp::JoinLine { x, y } => EditOp::SplitLine { x: *x, y: *y },
+        EditOp::InsertLine { y, line } => EditOp::RemoveLine {
+            y: *y,
+            line: line.clone(),
+        },
+        EditOp::RemoveLine { y, line } => EditOp::InsertLine {
+            y: *y,
+            line: line.clone(),
+        },
+    }
+}
+
+/// Where the cursor ends up right after `op` was applied.
+fn cursor_after_edit(op: &EditOp) -> (usize, usize) {
+    match op {
+        EditOp::Insert { x, y, text } => (*x + char_count(text), *y),
+        EditOp::Delete { x, y, .. } | EditOp::JoinLine { x, y } => (*x, *y),
+        EditOp::SplitLine { y, .. } => (0, *y + 1),
+        EditOp::InsertLine { y, .. } | EditOp::RemoveLine { y, .. } => (0, *y),
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Writes a file next to `file_path` with `write` and then renames it over it, so a save that
+/// fails partway leaves the old file as it was instead of cut short. A file the rename would
+/// change more than the contents of, or in a directory that can't be written in, is written
+/// in place instead.
+fn write_atomically<T>(
+    file_path: &str,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+) -> io::Result<T> {
+    // a link is kept a link, with the file it points to written instead
+    let path = fs::canonicalize(file_path).unwrap_or_else(|_| PathBuf::from(file_path));
+    if fs::metadata(&path).is_ok_and(|metadata| !replaceable(&metadata)) {
+        return write_in_place(&path, write);
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+    let Ok(mut temp_file) = File::create(&temp_path) else {
+        return write_in_place(&path, write);
+    };
+
+    let result = write(&mut temp_file).and_then(|written| {
+        temp_file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, &path)?;
+        Ok(written)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Whether a new file renamed over this one would be the same to everything else: it has no
+/// other names, which would keep the old contents, and it's ours, as the new one would be.
+#[cfg(unix)]
+fn replaceable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() <= 1 && metadata.uid() == unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn replaceable(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+/// Writes over the file at `path` itself with `write`, for when it can't be replaced by
+/// another. It's all put together first, as lines not kept in memory are read from the file.
+fn write_in_place<T>(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut bytes = Vec::new();
+    let written = write(&mut bytes)?;
+    let mut file = File::create(path)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    Ok(written)
+}
+
+/// What writing out a buffer came to.
+struct Written {
+    bytes: u64,
+    /// Whether every character fit in the encoding.
+    complete: bool,
+    /// Where each chunk of lines went in the file, for those read back the same from there.
+    stored: Vec<Option<Range<u64>>>,
+    /// The lines of the forgotten chunks that weren't, read to be written and kept since.
+    kept: Vec<(usize, Vec<String>)>,
+}
+
+/// Writes the text of `buffer` to `out`, with its own line endings and encoding. It goes a
+/// chunk of lines at a time, reading those not in memory as it gets to them, so a large file
+/// is never all held at once.
+fn write_text(
+    buffer: &Buffer,
+    ensure_final_newline: bool,
+    out: &mut dyn Write,
+) -> io::Result<Written> {
+    let ending = buffer.line_ending.as_str();
+    let final_newline = buffer.final_newline || ensure_final_newline;
+    let bom = buffer.encoding.bom();
+    out.write_all(bom)?;
+
+    let mut written = Written {
+        bytes: bom.len() as u64,
+        complete: true,
+        stored: Vec::new(),
+        kept: Vec::new(),
+    };
+    let mut y = 0;
+    for (chunk, lines) in buffer.content.chunks().enumerate() {
+        let mut text = String::new();
+        for line in lines.iter() {
+            text.push_str(line);
+            y += 1;
+            if y < buffer.content.len() || final_newline {
+                text.push_str(ending);
+            }
+        }
+        let (bytes, complete) = buffer.encoding.encode(&text);
+        out.write_all(&bytes)?;
+
+        let start = written.bytes;
+        written.bytes += bytes.len() as u64;
+        written.complete &= complete;
+        if complete && buffer.encoding.reads_in_place() {
+            written.stored.push(Some(start..written.bytes));
+        } else {
+            written.stored.push(None);
+            if let Cow::Owned(lines) = lines {
+                written.kept.push((chunk, lines));
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// The number of lines, words and characters in the selection, or the whole buffer if there
+/// isn't one. Words are runs of anything but whitespace, and each line break is a character.
+fn buffer_stats(editor: &Editor) -> (usize, usize, usize) {
+    let buffer = editor.current_buffer();
+    let len = buffer.content.len();
+    if len == 0 {
+        return (0, 0, 0);
+    }
+
+    // the row past the last line has nothing on it, so a selection there ends with the file
+    let ((start_x, start_y), (end_x, end_y)) = match buffer.selection() {
+        Some(((_, start_y), _)) if start_y >= len => return (0, 0, 0),
+        Some((start, end)) if end.1 < len => (start, end),
+        Some((start, _)) => (start, (usize::MAX, len - 1)),
+        None => ((0, 0), (usize::MAX, len - 1)),
+    };
+
+    let mut words = 0;
+    let mut chars = end_y - start_y;
+    for y in start_y..=end_y {
+        let line: Vec<char> = buffer.content.peek(y).unwrap().chars().collect();
+        let end = if y == end_y {
+            end_x.min(line.len())
+        } else {
+            line.len()
+        };
+        let start = if y == start_y { start_x.min(end) } else { 0 };
+        let line = &line[start..end];
+
+        chars += line.len();
+        words += line
+            .split(|c| c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .count();
+    }
+
+    (end_y - start_y + 1, words, chars)
+}
+
+/// The paths that `prefix` could be completed to, from the files in the directory it's in,
+/// sorted and with a `/` after directories. Hidden files are left out unless asked for.
+fn complete_path(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rsplit_once('/') {
+        Some((dir, name)) => (&prefix[..dir.len() + 1], name),
+        None => ("", prefix),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            // a link to a directory is completed like one
+            let is_dir = entry.path().is_dir();
+            Some(format!(
+                "{}{}{}",
+                dir,
+                file_name,
+                if is_dir { "/" } else { "" }
+            ))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Splits a line number off the end of `arg`, like the 42 of `file.rs:42`, unless there's a
+/// file by the whole name.
+fn split_line_number(arg: &str) -> (&str, Option<usize>) {
+    if Path::new(arg).exists() {
+        return (arg, None);
+    }
+
+    match arg.rsplit_once(':') {
+        Some((file_path, line)) if !file_path.is_empty() => match line.parse() {
+            Ok(line) => (file_path, Some(line)),
+            Err(_) => (arg, None),
+        },
+        _ => (arg, None),
+    }
+}
+
+/// The search prompt, with the options that are on and what's wrong with the query if it's
+/// a pattern that doesn't compile.
+fn search_prompt(search: &Search) -> String {
+    let flags = search.flags();
+    let mut text = match flags.is_empty() {
+        true => String::from("Search: {}"),
+        false => format!("Search {}: {{}}", flags),
+    };
+    match &search.error {
+        Some(error) => text.push_str(&format!(" ({})", error)),
+        None => text.push_str(" (ESC/Arrows/Enter, Ctrl-R regex, Ctrl-A any case, Ctrl-W word)"),
+    }
+    text
+}
+
+/// How many bytes of UTF-8 a file of `size` bytes is read as, when that's known up front.
+/// UTF-16 is turned into UTF-8 of some other length.
+/// Reads lines back from where they are in `file`, in `encoding`, the way they were read or
+/// saved. Lines end with `ending`, or with either one as the file is first read.
+fn line_source(file: File, encoding: Encoding, ending: Option<LineEnding>) -> Source<String> {
+    Box::new(move |range, len| {
+        let mut bytes = vec![0; (range.end - range.start) as usize];
+        // a file cut short since then is caught by load_lines, which stops saving over it
+        if read_at(&file, &mut bytes, range.start).is_err() {
+            bytes.clear();
+        }
+
+        let mut encoding = encoding;
+        let mut lines: Vec<String> = bytes
+            .split_inclusive(|&byte| byte == b'\n')
+            .take(len)
+            .map(|line| {
+                let mut line = decode_line(&mut encoding, line.to_vec());
+                strip_line_ending(&mut line, ending);
+                line
+            })
+            .collect();
+        lines.resize(len, String::new());
+        lines
+    })
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, bytes: &mut [u8], at: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.read_exact_at(bytes, at)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut bytes: &mut [u8], mut at: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !bytes.is_empty() {
+        match file.seek_read(bytes, at)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                bytes = &mut bytes[read..];
+                at += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Takes the line ending off the end of `line`, only `ending` if it's known which, and says
+/// which it was.
+fn strip_line_ending(line: &mut String, ending: Option<LineEnding>) -> Option<LineEnding> {
+    let found = if line.ends_with("\r\n") && ending != Some(LineEnding::Lf) {
+        LineEnding::CrLf
+    } else if line.ends_with('\n') && ending != Some(LineEnding::CrLf) {
+        LineEnding::Lf
+    } else {
+        return None;
+    };
+    line.truncate(line.len() - found.as_str().len());
+    Some(found)
+}
+
+fn utf8_size(encoding: Encoding, size: u64) -> Option<u64> {
+    match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be => None,
+        _ => Some(size),
+    }
+}
+
+fn percent(done: u64, total: u64) -> u64 {
+    (done * 100).checked_div(total).unwrap_or(100).min(100)
+}
+
+fn modified_time(file_path: &str) -> Option<SystemTime> {
+    fs::metadata(file_path).and_then(|m| m.modified()).ok()
+}
+
+/// Where the editor keeps the file `name` of what it remembers between sessions.
+fn state_path(name: &str) -> Option<PathBuf> {
+    let home = env::var("HOME").ok()?;
+    Some(Path::new(&home).join(".local/state/aricode").join(name))
+}
+
+/// Where the cursor positions of closed files are kept.
+fn positions_path() -> Option<PathBuf> {
+    state_path("positions")
+}
+
+/// The recently opened files, one per line, most recent first.
+fn read_recent_files() -> Vec<String> {
+    let Some(text) = state_path("recent").and_then(|path| fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+
+    text.lines().map(String::from).collect()
+}
+
+/// Puts `file_path` at the top of the recent files, taking it out from further down.
+fn remember_recent_file(file_path: &str) {
+    let Some(path) = state_path("recent") else {
+        return;
+    };
+
+    let key = position_key(file_path);
+    let mut files = read_recent_files();
+    files.retain(|recent| *recent != key);
+    files.insert(0, key);
+    files.truncate(MAX_RECENT_FILES);
+
+    let text: String = files.iter().map(|file| format!("{}\n", file)).collect();
+
+    // like the positions, a failure only costs a convenience
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(path, text);
+}
+
+/// The saved positions as `(path, x, y)`, from lines of `y x path`.
+fn read_positions() -> Vec<(String, usize, usize)> {
+    let Some(text) = positions_path().and_then(|path| fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let y = fields.next()?.parse().ok()?;
+            let x = fields.next()?.parse().ok()?;
+            Some((String::from(fields.next()?), x, y))
+        })
+        .collect()
+}
+
+/// The same file opened by different relative paths shares one saved position.
+fn position_key(file_path: &str) -> String {
+    match fs::canonicalize(file_path) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => String::from(file_path),
+    }
+}
+
+fn char_count(line: &str) -> usize {
+    line.chars().count()
+}
+
+/// Maps a cursor column, counted in characters, to its byte offset in the line.
+fn byte_index(line: &str, x: usize) -> usize {
+    line.char_indices().nth(x).map_or(line.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{ALT_ARROW_DOWN, SHIFT_ARROW_DOWN, SHIFT_ARROW_RIGHT};
+
+    /// An editor of 10 rows by 40 columns with a buffer holding `lines`, drawing to nowhere.
+    fn editor_with(lines: &[&str]) -> Editor {
+        let mut editor = Editor::new(10, 40);
+        editor.output = Box::new(io::sink());
+        editor.buffers.push(Buffer::default());
+        for (y, line) in lines.iter().enumerate() {
+            editor.insert_row(y, String::from(*line));
+        }
+        editor.current_buffer_mut().dirty = false;
+        editor
+    }
+
+    /// The key called `name` in the config file.
+    fn key(name: &str) -> u32 {
+        parse_key(name).unwrap()
+    }
+
+    fn cursor(editor: &Editor) -> (usize, usize) {
+        let buffer = editor.current_buffer();
+        (buffer.cursor_x, buffer.cursor_y)
+    }
+
+    fn content(editor: &Editor) -> Vec<&str> {
+        editor
+            .current_buffer()
+            .content
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn cursor_stays_on_the_text() {
+        let mut editor = editor_with(&["ab", "abcdef", ""]);
+        editor.move_cursor(ARROW_DOWN);
+        editor.move_cursor(END);
+        editor.move_cursor(ARROW_RIGHT);
+        assert_eq!(cursor(&editor), (6, 1));
+
+        // a shorter line pulls the cursor in, and it goes back out past it
+        editor.move_cursor(ARROW_UP);
+        assert_eq!(cursor(&editor), (2, 0));
+        editor.move_cursor(ARROW_DOWN);
+        assert_eq!(cursor(&editor), (6, 1));
+
+        editor.move_cursor(ARROW_DOWN);
+        editor.move_cursor(ARROW_DOWN);
+        editor.move_cursor(PAGE_DOWN);
+        assert_eq!(cursor(&editor), (0, 2));
+        // the column the cursor was pulled in from is still remembered
+        editor.move_cursor(ARROW_LEFT);
+        editor.move_cursor(ARROW_UP);
+        editor.move_cursor(ARROW_UP);
+        editor.move_cursor(ARROW_UP);
+        assert_eq!(cursor(&editor), (2, 0));
+    }
+
+    #[test]
+    fn view_scrolls_to_keep_the_cursor_on_screen() {
+        let line = "x".repeat(60);
+        let lines = vec![line.as_str(); 30];
+        let mut editor = editor_with(&lines);
+        let offsets = |editor: &Editor| {
+            let buffer = editor.current_buffer();
+            (buffer.offset_x, buffer.offset_y)
+        };
+
+        for _ in 0..9 {
+            editor.move_cursor(ARROW_DOWN);
+        }
+        editor.scroll();
+        assert_eq!(offsets(&editor), (0, 0));
+
+        // one row past the bottom edge, and one column past the right edge
+        editor.move_cursor(ARROW_DOWN);
+        for _ in 0..40 {
+            editor.move_cursor(ARROW_RIGHT);
+        }
+        editor.scroll();
+        assert_eq!(cursor(&editor), (40, 10));
+        assert_eq!(offsets(&editor), (1, 1));
+
+        editor.move_cursor(END);
+        editor.scroll();
+        assert_eq!(offsets(&editor), (21, 1));
+
+        editor.move_cursor(CTRL_END);
+        editor.scroll();
+        assert_eq!(cursor(&editor), (60, 29));
+        assert_eq!(offsets(&editor), (21, 20));
+
+        editor.move_cursor(CTRL_HOME);
+        editor.scroll();
+        assert_eq!(offsets(&editor), (0, 0));
+    }
+
+    #[test]
+    fn enter_splits_the_line_at_the_cursor() {
+        // at the start of the line, in the middle and at the end
+        for (x, expected) in [
+            (0, ["", "one", "two"]),
+            (1, ["o", "ne", "two"]),
+            (3, ["one", "", "two"]),
+        ] {
+            let mut editor = editor_with(&["one", "two"]);
+            editor.current_buffer_mut().cursor_x = x;
+            editor.insert_newline();
+            assert_eq!(content(&editor), expected, "at {}", x);
+            assert_eq!(cursor(&editor), (0, 1), "at {}", x);
+        }
+
+        // an indented line keeps its indentation on the line split off it
+        let mut editor = editor_with(&["    one"]);
+        editor.current_buffer_mut().cursor_x = 5;
+        editor.break_line();
+        assert_eq!(content(&editor), ["    o", "ne"]);
+        editor.undo();
+        editor.current_buffer_mut().cursor_x = 5;
+        editor.insert_newline();
+        assert_eq!(content(&editor), ["    o", "    ne"]);
+        assert_eq!(cursor(&editor), (4, 1));
+    }
+
+    #[test]
+    fn enter_in_an_empty_buffer() {
+        let mut editor = editor_with(&[]);
+        editor.insert_newline();
+        assert_eq!(content(&editor), ["", ""]);
+        assert_eq!(cursor(&editor), (0, 1));
+
+        editor.handle_key(ARROW_UP);
+        editor.handle_key(ARROW_DOWN);
+        assert_eq!(cursor(&editor), (0, 1));
+
+        // the line break is undone along with the line it was made in
+        editor.undo();
+        assert!(content(&editor).is_empty());
+    }
+
+    #[test]
+    fn special_keys_are_never_typed() {
+        for lines in [&[][..], &["hello"][..]] {
+            let mut editor = editor_with(lines);
+            // every key read from an escape sequence, from the mouse, or made up by the editor
+            for key in ARROW_UP..=ALT_ARROW_DOWN {
+                editor.handle_key(key);
+                assert_eq!(content(&editor), lines, "after {:#x}", key);
+                let (x, y) = cursor(&editor);
+                assert!(y <= lines.len() && x <= lines.first().map_or(0, |line| line.len()));
+            }
+
+            // none of them left a paste, the help or a two key command going
+            editor.handle_key(key("ctrl-home"));
+            editor.handle_key('!' as u32);
+            let typed = format!("!{}", lines.first().unwrap_or(&""));
+            assert_eq!(content(&editor), [typed.as_str()]);
+        }
+    }
+
+    #[test]
+    fn arrow_down_stops_at_the_last_line() {
+        let mut editor = editor_with(&["one", "three", "go"]);
+        editor.current_buffer_mut().cursor_x = 5;
+        for _ in 0..5 {
+            editor.handle_key(ARROW_DOWN);
+        }
+        assert_eq!(cursor(&editor), (2, 2));
+
+        let mut editor = editor_with(&[]);
+        editor.handle_key(ARROW_DOWN);
+        assert_eq!(cursor(&editor), (0, 0));
+    }
+
+    #[test]
+    fn pasted_crlf_breaks_the_line_once() {
+        let mut editor = editor_with(&[]);
+        editor.handle_key(PASTE_START);
+        for key in "a\r\nb\rc\n\nd".chars() {
+            editor.handle_key(key as u32);
+        }
+        editor.handle_key(PASTE_END);
+        assert_eq!(content(&editor), ["a", "b", "c", "", "d"]);
+    }
+
+    #[test]
+    fn ctrl_c_cancels_the_selection_before_quitting() {
+        let mut editor = editor_with(&["hello"]);
+        editor.handle_key(SHIFT_ARROW_RIGHT);
+        editor.handle_key(SHIFT_ARROW_RIGHT);
+        editor.set_status_message("Search: hel");
+        editor.handle_key(CTRL_C);
+        assert_eq!(editor.status_message, "");
+
+        editor.handle_key('x' as u32);
+        assert_eq!(content(&editor), ["hexllo"]);
+
+        // a command started in normal mode is dropped too
+        editor.mode = Mode::Normal;
+        editor.handle_key('d' as u32);
+        editor.handle_key(CTRL_C);
+        editor.handle_key('d' as u32);
+        assert_eq!(content(&editor), ["hexllo"]);
+
+        // with nothing to cancel the next press would quit
+        editor.handle_key(ESCAPE);
+        editor.handle_key(CTRL_C);
+        assert_eq!(editor.status_message, "Press Ctrl-C again to quit");
+    }
+
+    #[test]
+    fn normal_mode_edits_can_be_undone() {
+        let mut editor = editor_with(&[]);
+        for key in "one\rtwo".chars() {
+            editor.handle_key(key as u32);
+        }
+        editor.mode = Mode::Normal;
+        editor.handle_key('d' as u32);
+        editor.handle_key('d' as u32);
+        assert_eq!(content(&editor), ["one"]);
+        editor.handle_key(key("ctrl-z"));
+        assert_eq!(content(&editor), ["one", "two"]);
+
+        editor.handle_key(HOME);
+        editor.handle_key('x' as u32);
+        assert_eq!(content(&editor), ["one", "wo"]);
+        editor.handle_key('u' as u32);
+        assert_eq!(content(&editor), ["one", "two"]);
+    }
+
+    #[test]
+    fn tab_indents_a_single_selected_line() {
+        let mut editor = editor_with(&["one", "two", "three"]);
+        editor.handle_key(SHIFT_ARROW_DOWN);
+        editor.handle_key(TAB);
+        assert_eq!(content(&editor), ["\tone", "two", "three"]);
+    }
+
+    #[test]
+    fn duplicate_copies_a_single_selected_line() {
+        let mut editor = editor_with(&["one", "two", "three"]);
+        editor.handle_key(SHIFT_ARROW_DOWN);
+        editor.handle_key(key("ctrl-d"));
+        assert_eq!(content(&editor), ["one", "one", "two", "three"]);
+    }
+
+    #[test]
+    fn move_down_moves_a_single_selected_line() {
+        let mut editor = editor_with(&["one", "two", "three"]);
+        editor.handle_key(SHIFT_ARROW_DOWN);
+        editor.handle_key(key("alt-down"));
+        assert_eq!(content(&editor), ["two", "one", "three"]);
+        assert_eq!(editor.current_buffer().selection(), Some(((0, 1), (0, 2))));
+    }
+
+    #[test]
+    fn toggle_comment_comments_a_single_selected_line() {
+        let mut editor = editor_with(&["one", "two", "three"]);
+        editor.current_buffer_mut().file_path = String::from("lines.rs");
+        editor.handle_key(SHIFT_ARROW_DOWN);
+        editor.handle_key(key("ctrl-\\"));
+        assert_eq!(content(&editor), ["// one", "two", "three"]);
+    }
+
+    #[test]
+    fn normal_mode_keys_move_instead_of_editing() {
+        let mut editor = editor_with(&["one", "  two"]);
+        editor.modal = true;
+        editor.mode = Mode::Normal;
+        editor.current_buffer_mut().cursor_x = 2;
+
+        editor.handle_key(BACKSPACE);
+        assert_eq!(editor.current_buffer().cursor_x, 1);
+        editor.handle_key(ENTER);
+        assert_eq!(cursor(&editor), (2, 1));
+        for key in [TAB, key("delete"), key("ctrl-u"), key("ctrl-d")] {
+            editor.handle_key(key);
+        }
+        // a paste, which is thrown away rather than typed
+        for key in [PASTE_START, 'x' as u32, PASTE_END] {
+            editor.handle_key(key);
+        }
+
+        assert_eq!(content(&editor), ["one", "  two"]);
+        assert!(!editor.current_buffer().dirty);
+    }
+
+    /// A directory of its own for a test to write files in.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("aricode-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Points HOME at a directory of the tests' own, so the recent files and cursor positions
+    /// remembered by opening files don't end up in the real one.
+    fn keep_state_apart() {
+        let home = env::temp_dir().join(format!("aricode-home-{}", process::id()));
+        env::set_var("HOME", home);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saving_keeps_hard_links() {
+        let dir = temp_dir("links");
+        let (file, link) = (dir.join("file"), dir.join("link"));
+        fs::write(&file, "old\n").unwrap();
+        fs::hard_link(&file, &link).unwrap();
+
+        write_atomically(file.to_str().unwrap(), |out| out.write_all(b"new\n")).unwrap();
+
+        assert_eq!(fs::read_to_string(&link).unwrap(), "new\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn undo_after_enter_keeps_the_line_break() {
+        let mut editor = editor_with(&[]);
+        for key in ['p' as u32, ENTER, 'q' as u32, ' ' as u32] {
+            editor.handle_key(key);
+        }
+        assert_eq!(content(&editor), ["p", "q "]);
+
+        editor.handle_key(key("ctrl-z"));
+        assert_eq!(content(&editor), ["p", ""]);
+        editor.handle_key(key("ctrl-z"));
+        assert_eq!(content(&editor), ["p"]);
+    }
+
+    #[test]
+    fn latin1_file_is_saved_as_it_was_read() {
+        let dir = temp_dir("latin1");
+        keep_state_apart();
+        let file = dir.join("file.txt");
+        // only the start is checked up front, so the bad byte comes up while reading
+        let mut bytes = "café\n".repeat(200_000).into_bytes();
+        bytes.extend(b"\xef\n");
+        fs::write(&file, &bytes).unwrap();
+
+        let mut editor = editor_with(&[]);
+        editor.buffers.clear();
+        editor.open_file(file.to_str().unwrap());
+        assert!(editor.current_buffer().encoding == Encoding::Utf8);
+        editor.handle_key(key("end"));
+        editor.load_lines(usize::MAX);
+
+        assert!(editor.current_buffer().encoding == Encoding::Latin1);
+        assert_eq!(editor.current_buffer().content[0], "cafÃ©");
+        assert_eq!(cursor(&editor), (5, 0));
+        editor.handle_key('x' as u32);
+        editor.handle_key(BACKSPACE);
+        editor.save_file();
+
+        assert_eq!(fs::read(&file).unwrap(), bytes);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn file_rewritten_while_being_read_isnt_saved_over() {
+        let dir = temp_dir("rewritten");
+        keep_state_apart();
+        let file = dir.join("file.txt");
+        fs::write(&file, "old\n".repeat(LOAD_AHEAD_LINES * 2)).unwrap();
+
+        let mut editor = editor_with(&[]);
+        editor.buffers.clear();
+        editor.open_file(file.to_str().unwrap());
+        // written over in place, as the same file
+        fs::write(&file, "new\n").unwrap();
+        editor.handle_key(key("ctrl-end"));
+
+        assert_eq!(editor.current_buffer().content.len(), LOAD_AHEAD_LINES);
+        assert!(editor
+            .current_buffer()
+            .content
+            .iter()
+            .all(|line| line == "old"));
+        editor.save_file();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn far_lines_are_read_again_and_saved() {
+        let dir = temp_dir("far");
+        keep_state_apart();
+        let file = dir.join("file.txt");
+        let lines = KEPT_LINES * 5;
+        let text: String = (0..lines).map(|y| format!("line {}\r\n", y)).collect();
+        fs::write(&file, &text).unwrap();
+
+        let mut editor = editor_with(&[]);
+        editor.buffers.clear();
+        editor.open_file(file.to_str().unwrap());
+        editor.handle_key('x' as u32);
+        editor.handle_key(key("ctrl-end"));
+        editor.scroll();
+        assert_eq!(editor.current_buffer().content.len(), lines);
+        assert!(editor.current_buffer().content.has_forgotten());
+        assert_eq!(
+            editor.current_buffer().content[KEPT_LINES * 2],
+            "line 20000"
+        );
+
+        // the changed line stays in memory, and the rest is read from the file to save it
+        editor.save_file();
+        let saved = format!("x{}", text);
+        assert_eq!(fs::read_to_string(&file).unwrap(), saved);
+
+        // and is read back from the file saved from then on
+        editor.scroll();
+        assert!(editor.current_buffer().content.has_forgotten());
+        assert_eq!(editor.current_buffer().content[0], "xline 0");
+        editor.handle_key(key("ctrl-home"));
+        editor.handle_key(key("delete"));
+        editor.save_file();
+        assert_eq!(fs::read_to_string(&file).unwrap(), text);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,213 @@
+//! The encodings files are read and written in.
+
+use std::io;
+use std::io::{BufRead, Cursor, Read};
+
+/// How much of a file is checked for being UTF-8 before it's opened.
+const CHECKED_BYTES: u64 = 1024 * 1024;
+
+/// How a file's text is stored, so it's saved back the same way.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 that starts with a byte order mark.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// Any file that isn't valid UTF-8, where every byte is read as the character it numbers.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+
+    /// The encoding called `name`, like `utf-8` or `latin1`, ignoring case and dashes.
+    pub fn parse(name: &str) -> Option<Encoding> {
+        let name: String = name
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .collect();
+        match name.to_ascii_lowercase().as_str() {
+            "utf8" => Some(Encoding::Utf8),
+            "utf8bom" => Some(Encoding::Utf8Bom),
+            "utf16le" => Some(Encoding::Utf16Le),
+            "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "iso88591" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// The byte order mark a file in this encoding starts with, if it has one.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => &[0xef, 0xbb, 0xbf],
+            Encoding::Utf16Le => &[0xff, 0xfe],
+            Encoding::Utf16Be => &[0xfe, 0xff],
+            Encoding::Utf8 | Encoding::Latin1 => &[],
+        }
+    }
+
+    /// Whether a file's lines can be found by where they are in it, to be read again from
+    /// there. UTF-16 is decoded up front instead, as its lines aren't split on newline bytes.
+    pub fn reads_in_place(self) -> bool {
+        !matches!(self, Encoding::Utf16Le | Encoding::Utf16Be)
+    }
+
+    /// The bytes `text` is saved as, after the byte order mark, and whether it all fit.
+    /// Latin-1 has no room for most characters, which are written as `?` instead.
+    pub fn encode(self, text: &str) -> (Vec<u8>, bool) {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => (text.as_bytes().to_vec(), true),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut bytes = Vec::with_capacity(text.len() * 2);
+                for unit in text.encode_utf16() {
+                    match self {
+                        Encoding::Utf16Le => bytes.extend(unit.to_le_bytes()),
+                        _ => bytes.extend(unit.to_be_bytes()),
+                    }
+                }
+                (bytes, true)
+            }
+            Encoding::Latin1 => {
+                let mut complete = true;
+                let bytes = text
+                    .chars()
+                    .map(|c| {
+                        u8::try_from(c).unwrap_or_else(|_| {
+                            complete = false;
+                            b'?'
+                        })
+                    })
+                    .collect();
+                (bytes, complete)
+            }
+        }
+    }
+}
+
+/// Looks at how `reader` starts to tell its encoding. UTF-16 is decoded up front and read
+/// on as UTF-8, since its lines can't be split on a newline byte.
+pub fn open_reader(mut reader: Box<dyn BufRead>) -> io::Result<(Box<dyn BufRead>, Encoding)> {
+    let start = reader.fill_buf()?;
+
+    let encoding = if start.starts_with(&[0xef, 0xbb, 0xbf]) {
+        reader.consume(3);
+        return Ok((reader, Encoding::Utf8Bom));
+    } else if start.starts_with(&[0xff, 0xfe]) {
+        Encoding::Utf16Le
+    } else if start.starts_with(&[0xfe, 0xff]) {
+        Encoding::Utf16Be
+    } else {
+        return Ok((reader, Encoding::Utf8));
+    };
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let units: Vec<u16> = bytes[2..]
+        .chunks(2)
+        .map(|pair| {
+            let pair = [pair[0], pair.get(1).copied().unwrap_or(0)];
+            match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes(pair),
+                _ => u16::from_be_bytes(pair),
+            }
+        })
+        .collect();
+    let text = String::from_utf16_lossy(&units);
+
+    Ok((Box::new(Cursor::new(text.into_bytes())), encoding))
+}
+
+/// Reads the start of `reader` to tell whether it's valid UTF-8, without keeping more than a
+/// piece of it at a time. Only so much is read, so a large file opens right away. The rest is
+/// checked line by line as it's read, by `decode_line`.
+pub fn starts_as_utf8(reader: impl Read) -> io::Result<bool> {
+    let mut reader = reader.take(CHECKED_BYTES);
+    let mut chunk = vec![0; 64 * 1024];
+    let mut total = 0;
+    // the start of a character cut off at the end of the last piece
+    let mut carried = 0;
+
+    loop {
+        let read = match reader.read(&mut chunk[carried..]) {
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if read == 0 {
+            // a character cut off where the check stops goes on past it
+            return Ok(carried == 0 || total == CHECKED_BYTES);
+        }
+        total += read as u64;
+
+        let end = carried + read;
+        carried = match std::str::from_utf8(&chunk[..end]) {
+            Ok(_) => 0,
+            Err(err) if err.error_len().is_none() => end - err.valid_up_to(),
+            Err(_) => return Ok(false),
+        };
+        chunk.copy_within(end - carried..end, 0);
+    }
+}
+
+/// Turns a line read from a file into text. A file thought to be UTF-8 turns out to be
+/// Latin-1 at its first line that isn't valid UTF-8, and `encoding` is changed to match. That
+/// happens past the start checked by `starts_as_utf8`, or to a file changed since.
+pub fn decode_line(encoding: &mut Encoding, bytes: Vec<u8>) -> String {
+    if *encoding == Encoding::Latin1 {
+        return latin1(&bytes);
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(line) => line,
+        Err(err) if *encoding == Encoding::Utf8 => {
+            *encoding = Encoding::Latin1;
+            latin1(err.as_bytes())
+        }
+        Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+    }
+}
+
+/// The text of `bytes` read as Latin-1, each byte the character it numbers.
+pub fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_as_utf8_reads_characters_across_pieces() {
+        // a two byte character right where the first piece ends
+        let mut text = vec![b'a'; 64 * 1024 - 1];
+        text.extend("é".as_bytes());
+        assert!(starts_as_utf8(Cursor::new(&text)).unwrap());
+
+        text.push(0xef);
+        assert!(!starts_as_utf8(Cursor::new(&text)).unwrap());
+        text.pop();
+        text.push(0xe9);
+        assert!(!starts_as_utf8(Cursor::new(&text)).unwrap());
+    }
+
+    #[test]
+    fn starts_as_utf8_stops_at_the_start() {
+        // a character cut off by the end of the check, and a bad byte past it
+        let mut text = vec![b'a'; CHECKED_BYTES as usize - 1];
+        text.extend("é".as_bytes());
+        assert!(starts_as_utf8(Cursor::new(&text)).unwrap());
+        text.push(0xe9);
+        assert!(starts_as_utf8(Cursor::new(&text)).unwrap());
+    }
+}
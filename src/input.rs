@@ -0,0 +1,233 @@
+//! Turning the bytes typed at the terminal into keys.
+
+use std::char;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::terminal::{read_byte, InputBuffer, WINDOW_SIZE_CHANGED};
+
+// special keys are numbered past the last unicode scalar value, so they can't be
+// mistaken for a typed character
+pub const ARROW_UP: u32 = 0x110000;
+pub const ARROW_DOWN: u32 = 0x110001;
+pub const ARROW_RIGHT: u32 = 0x110002;
+pub const ARROW_LEFT: u32 = 0x110003;
+pub const HOME: u32 = 0x110004;
+pub const END: u32 = 0x110005;
+pub const DEL: u32 = 0x110006;
+pub const PAGE_UP: u32 = 0x110007;
+pub const PAGE_DOWN: u32 = 0x110008;
+pub const WINDOW_RESIZED: u32 = 0x110009;
+pub const CTRL_PAGE_UP: u32 = 0x11000a;
+pub const CTRL_PAGE_DOWN: u32 = 0x11000b;
+pub const CTRL_ARROW_RIGHT: u32 = 0x11000c;
+pub const CTRL_ARROW_LEFT: u32 = 0x11000d;
+pub const PASTE_START: u32 = 0x11000e;
+pub const PASTE_END: u32 = 0x11000f;
+// mouse events, with where they happened left in `InputBuffer::mouse`
+pub const MOUSE_CLICK: u32 = 0x110010;
+pub const MOUSE_WHEEL_UP: u32 = 0x110011;
+pub const MOUSE_WHEEL_DOWN: u32 = 0x110012;
+pub const MOUSE_IGNORED: u32 = 0x110013;
+pub const SHIFT_ARROW_UP: u32 = 0x110014;
+pub const SHIFT_ARROW_DOWN: u32 = 0x110015;
+pub const SHIFT_ARROW_RIGHT: u32 = 0x110016;
+pub const SHIFT_ARROW_LEFT: u32 = 0x110017;
+pub const F1: u32 = 0x110018;
+/// Nothing was typed for `IDLE_TIMEOUT`, a chance to look for changes outside the editor.
+pub const IDLE: u32 = 0x110019;
+pub const INSERT: u32 = 0x11001a;
+pub const SHIFT_TAB: u32 = 0x11001b;
+pub const CTRL_HOME: u32 = 0x11001c;
+pub const CTRL_END: u32 = 0x11001d;
+pub const ALT_ARROW_UP: u32 = 0x11001e;
+pub const ALT_ARROW_DOWN: u32 = 0x11001f;
+
+pub const ENTER: u32 = 0x0d;
+pub const ESCAPE: u32 = 0x1b;
+pub const BACKSPACE: u32 = 0x7f;
+pub const TAB: u32 = 0x09;
+pub const CTRL_A: u32 = 0x01;
+pub const CTRL_C: u32 = 0x03;
+pub const CTRL_R: u32 = 0x12;
+pub const CTRL_W: u32 = 0x17;
+pub const CTRL_BACKSLASH: u32 = 0x1c;
+pub const CTRL_CLOSE_BRACKET: u32 = 0x1d;
+pub const CTRL_SLASH: u32 = 0x1f;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait for the rest of an escape sequence before taking it as the Escape key.
+/// A terminal sends a whole sequence at once, so anything slower was typed.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub fn read_key(input: &mut InputBuffer) -> u32 {
+    let Some(key) = read_until(input, Some(Instant::now() + IDLE_TIMEOUT)) else {
+        return IDLE;
+    };
+    if key == '\0' {
+        return WINDOW_RESIZED;
+    }
+
+    if key != '\x1b' {
+        return read_utf8_char(input, key as u8) as u32;
+    }
+
+    let Some(next_key) = read_until(input, Some(Instant::now() + ESCAPE_TIMEOUT)) else {
+        return ESCAPE;
+    };
+    if next_key != '[' && next_key != 'O' {
+        return next_key as u32;
+    }
+
+    let yet_another_key = read(input);
+    if next_key == '[' {
+        match yet_another_key {
+            'A' => ARROW_UP,
+            'B' => ARROW_DOWN,
+            'C' => ARROW_RIGHT,
+            'D' => ARROW_LEFT,
+            'H' => HOME,
+            'F' => END,
+            'Z' => SHIFT_TAB,
+            '<' => read_mouse(input),
+            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
+                let yet_another_char = read(input);
+                if yet_another_char == ';' {
+                    // a key pressed together with a modifier, like \x1b[5;5~ for Ctrl-PageUp
+                    let modifier = read(input);
+                    let last_char = read(input);
+
+                    return match (yet_another_key, modifier, last_char) {
+                        ('5', '5', '~') => CTRL_PAGE_UP,
+                        ('6', '5', '~') => CTRL_PAGE_DOWN,
+                        ('1', '5', 'C') => CTRL_ARROW_RIGHT,
+                        ('1', '5', 'H') => CTRL_HOME,
+                        ('1', '5', 'F') => CTRL_END,
+                        ('1', '5', 'D') => CTRL_ARROW_LEFT,
+                        ('1', '2', 'A') => SHIFT_ARROW_UP,
+                        ('1', '2', 'B') => SHIFT_ARROW_DOWN,
+                        ('1', '2', 'C') => SHIFT_ARROW_RIGHT,
+                        ('1', '2', 'D') => SHIFT_ARROW_LEFT,
+                        ('1', '3', 'A') => ALT_ARROW_UP,
+                        ('1', '3', 'B') => ALT_ARROW_DOWN,
+                        _ => last_char as u32,
+                    };
+                }
+
+                if yet_another_char.is_ascii_digit() {
+                    // a longer code, like \x1b[200~ which marks the start of pasted text
+                    let mut code = String::from(yet_another_key);
+                    code.push(yet_another_char);
+                    let mut last_char = read(input);
+                    while last_char.is_ascii_digit() {
+                        code.push(last_char);
+                        last_char = read(input);
+                    }
+
+                    return match (code.as_str(), last_char) {
+                        ("200", '~') => PASTE_START,
+                        ("201", '~') => PASTE_END,
+                        ("11", '~') => F1,
+                        _ => last_char as u32,
+                    };
+                }
+
+                if yet_another_char != '~' {
+                    return yet_another_char as u32;
+                }
+
+                match yet_another_key {
+                    '1' | '7' => HOME,
+                    '2' => INSERT,
+                    '3' => DEL,
+                    '4' | '8' => END,
+                    '5' => PAGE_UP,
+                    '6' => PAGE_DOWN,
+                    _ => yet_another_key as u32,
+                }
+            }
+            _ => yet_another_key as u32,
+        }
+    } else {
+        // nextKey == O
+        match yet_another_key {
+            'H' => HOME,
+            'F' => END,
+            'P' => F1,
+            _ => yet_another_key as u32,
+        }
+    }
+}
+
+/// Reads the rest of an SGR mouse report, like \x1b[<0;12;5M for a click at column 12 of
+/// row 5.
+fn read_mouse(input: &mut InputBuffer) -> u32 {
+    let mut fields = [0usize; 3];
+    let mut field = 0;
+
+    let last_char = loop {
+        let c = read(input);
+        match c.to_digit(10) {
+            Some(digit) => fields[field] = fields[field] * 10 + digit as usize,
+            None if c == ';' && field < 2 => field += 1,
+            None => break c,
+        }
+    };
+
+    let [button, column, row] = fields;
+    input.mouse = (row.saturating_sub(1), column.saturating_sub(1));
+
+    // 'M' is a press and 'm' a release, which isn't of any use
+    match (button, last_char) {
+        (0, 'M') => MOUSE_CLICK,
+        (64, 'M') => MOUSE_WHEEL_UP,
+        (65, 'M') => MOUSE_WHEEL_DOWN,
+        _ => MOUSE_IGNORED,
+    }
+}
+
+pub fn printable_char(key: u32) -> Option<char> {
+    char::from_u32(key).filter(|c| !c.is_control())
+}
+
+fn read(input: &mut InputBuffer) -> char {
+    read_until(input, None).unwrap_or('\0')
+}
+
+/// Waits for the next byte, giving up at `deadline` if there is one.
+fn read_until(input: &mut InputBuffer, deadline: Option<Instant>) -> Option<char> {
+    loop {
+        // give the caller a chance to redraw when the terminal is resized
+        if WINDOW_SIZE_CHANGED.load(Ordering::SeqCst) {
+            return Some('\0');
+        }
+
+        if let Some(byte) = read_byte(input).filter(|&byte| byte != 0) {
+            return Some(byte as char);
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None;
+        }
+    }
+}
+
+fn read_utf8_char(input: &mut InputBuffer, first_byte: u8) -> char {
+    let len = match first_byte {
+        0x00..=0x7f => return first_byte as char,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return char::REPLACEMENT_CHARACTER,
+    };
+
+    let mut bytes = vec![first_byte];
+    while bytes.len() < len {
+        bytes.push(read(input) as u8);
+    }
+
+    std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
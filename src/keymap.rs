@@ -0,0 +1,404 @@
+//! What the keys do: the actions there are, and which key runs which.
+
+use std::collections::HashMap;
+
+use crate::input::{
+    ALT_ARROW_DOWN, ALT_ARROW_UP, ARROW_DOWN, ARROW_LEFT, ARROW_RIGHT, ARROW_UP, BACKSPACE,
+    CTRL_ARROW_LEFT, CTRL_ARROW_RIGHT, CTRL_BACKSLASH, CTRL_CLOSE_BRACKET, CTRL_END, CTRL_HOME,
+    CTRL_PAGE_DOWN, CTRL_PAGE_UP, CTRL_SLASH, DEL, END, ENTER, ESCAPE, F1, HOME, INSERT, PAGE_DOWN,
+    PAGE_UP, SHIFT_ARROW_DOWN, SHIFT_ARROW_LEFT, SHIFT_ARROW_RIGHT, SHIFT_ARROW_UP, SHIFT_TAB, TAB,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Save,
+    Quit,
+    Cancel,
+    Open,
+    Find,
+    Replace,
+    GotoLine,
+    Undo,
+    Redo,
+    CutToLineEnd,
+    DeleteLine,
+    Duplicate,
+    MoveLineUp,
+    MoveLineDown,
+    Paste,
+    MatchingBracket,
+    LineNumbers,
+    Wrap,
+    Whitespace,
+    Overwrite,
+    Help,
+    RecentFiles,
+    NextBuffer,
+    PreviousBuffer,
+    Up,
+    Down,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    LineStart,
+    LineEnd,
+    FileStart,
+    FileEnd,
+    PageUp,
+    PageDown,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    Newline,
+    Backspace,
+    Delete,
+    Tab,
+    Dedent,
+    ToggleComment,
+    Rewrap,
+    Format,
+    Stats,
+    Command,
+}
+
+impl Action {
+    /// Whether the action changes the text, so it's refused in a read-only buffer.
+    pub fn edits(self) -> bool {
+        matches!(
+            self,
+            Action::Replace
+                | Action::Undo
+                | Action::Redo
+                | Action::CutToLineEnd
+                | Action::DeleteLine
+                | Action::Duplicate
+                | Action::MoveLineUp
+                | Action::MoveLineDown
+                | Action::Paste
+                | Action::Newline
+                | Action::Backspace
+                | Action::Delete
+                | Action::Tab
+                | Action::Dedent
+                | Action::ToggleComment
+                | Action::Rewrap
+                | Action::Format
+        )
+    }
+}
+
+/// Every action with the name it goes by in the config file and what the help screen says
+/// about it. Moving around and the keys that type don't need explaining, so they have none.
+const ACTIONS: &[(Action, &str, Option<&str>)] = &[
+    (Action::Save, "save", Some("Save")),
+    (Action::Quit, "quit", Some("Quit")),
+    (
+        Action::Cancel,
+        "cancel",
+        Some("Cancel, or quit when pressed twice"),
+    ),
+    (Action::Open, "open", Some("Open a file")),
+    (Action::Find, "find", Some("Find")),
+    (Action::Replace, "replace", Some("Find and replace")),
+    (Action::GotoLine, "goto-line", Some("Go to line")),
+    (Action::Undo, "undo", Some("Undo")),
+    (Action::Redo, "redo", Some("Redo")),
+    (
+        Action::CutToLineEnd,
+        "cut-to-line-end",
+        Some("Cut to the end of the line"),
+    ),
+    (Action::DeleteLine, "delete-line", Some("Delete the line")),
+    (
+        Action::Duplicate,
+        "duplicate",
+        Some("Duplicate the line or the selected lines"),
+    ),
+    (
+        Action::MoveLineUp,
+        "move-line-up",
+        Some("Move the line or the selected lines up"),
+    ),
+    (
+        Action::MoveLineDown,
+        "move-line-down",
+        Some("Move the line or the selected lines down"),
+    ),
+    (Action::Paste, "paste", Some("Paste what was cut")),
+    (
+        Action::MatchingBracket,
+        "matching-bracket",
+        Some("Jump to the matching bracket"),
+    ),
+    (
+        Action::LineNumbers,
+        "line-numbers",
+        Some("Show or hide line numbers"),
+    ),
+    (
+        Action::Wrap,
+        "wrap",
+        Some("Wrap long lines or scroll sideways"),
+    ),
+    (
+        Action::Whitespace,
+        "whitespace",
+        Some("Show or hide spaces and tabs"),
+    ),
+    (
+        Action::Overwrite,
+        "overwrite",
+        Some("Type over the text or insert into it"),
+    ),
+    (Action::Help, "help", Some("Show this help")),
+    (
+        Action::RecentFiles,
+        "recent-files",
+        Some("Reopen a recent file"),
+    ),
+    (Action::NextBuffer, "next-buffer", Some("Next open file")),
+    (
+        Action::PreviousBuffer,
+        "previous-buffer",
+        Some("Previous open file"),
+    ),
+    (Action::Up, "up", None),
+    (Action::Down, "down", None),
+    (Action::Left, "left", None),
+    (Action::Right, "right", None),
+    (
+        Action::WordLeft,
+        "word-left",
+        Some("Move to the previous word"),
+    ),
+    (
+        Action::WordRight,
+        "word-right",
+        Some("Move to the next word"),
+    ),
+    (Action::LineStart, "line-start", None),
+    (Action::LineEnd, "line-end", None),
+    (
+        Action::FileStart,
+        "file-start",
+        Some("Go to the start of the file"),
+    ),
+    (
+        Action::FileEnd,
+        "file-end",
+        Some("Go to the end of the file"),
+    ),
+    (Action::PageUp, "page-up", None),
+    (Action::PageDown, "page-down", None),
+    (Action::SelectUp, "select-up", Some("Select up")),
+    (Action::SelectDown, "select-down", Some("Select down")),
+    (Action::SelectLeft, "select-left", Some("Select left")),
+    (Action::SelectRight, "select-right", Some("Select right")),
+    (Action::Newline, "newline", None),
+    (Action::Backspace, "backspace", None),
+    (Action::Delete, "delete", None),
+    (
+        Action::Tab,
+        "tab",
+        Some("Indent the selected lines, or insert a tab"),
+    ),
+    (
+        Action::Dedent,
+        "dedent",
+        Some("Unindent the selected lines"),
+    ),
+    (
+        Action::ToggleComment,
+        "toggle-comment",
+        Some("Comment or uncomment the selected lines"),
+    ),
+    (
+        Action::Rewrap,
+        "rewrap",
+        Some("Rewrap the paragraph to the fill column"),
+    ),
+    (
+        Action::Format,
+        "format",
+        Some("Format the file with its formatter"),
+    ),
+    (
+        Action::Stats,
+        "stats",
+        Some("Count the lines, words and characters"),
+    ),
+    (
+        Action::Command,
+        "command",
+        Some("Run a command: w [file], q, wq, e file, set name=value"),
+    ),
+];
+
+/// The keys that have names of their own. Ctrl with a letter is `ctrl-` and the letter, and
+/// any other character is itself.
+const KEY_NAMES: &[(&str, u32)] = &[
+    ("up", ARROW_UP),
+    ("down", ARROW_DOWN),
+    ("left", ARROW_LEFT),
+    ("right", ARROW_RIGHT),
+    ("home", HOME),
+    ("end", END),
+    ("pageup", PAGE_UP),
+    ("pagedown", PAGE_DOWN),
+    ("delete", DEL),
+    ("insert", INSERT),
+    ("backspace", BACKSPACE),
+    ("enter", ENTER),
+    ("escape", ESCAPE),
+    ("tab", TAB),
+    ("shift-tab", SHIFT_TAB),
+    ("f1", F1),
+    ("ctrl-pageup", CTRL_PAGE_UP),
+    ("ctrl-pagedown", CTRL_PAGE_DOWN),
+    ("ctrl-left", CTRL_ARROW_LEFT),
+    ("ctrl-right", CTRL_ARROW_RIGHT),
+    ("ctrl-home", CTRL_HOME),
+    ("ctrl-end", CTRL_END),
+    ("alt-up", ALT_ARROW_UP),
+    ("alt-down", ALT_ARROW_DOWN),
+    ("shift-up", SHIFT_ARROW_UP),
+    ("shift-down", SHIFT_ARROW_DOWN),
+    ("shift-left", SHIFT_ARROW_LEFT),
+    ("shift-right", SHIFT_ARROW_RIGHT),
+    ("ctrl-]", CTRL_CLOSE_BRACKET),
+    ("ctrl-/", CTRL_SLASH),
+    ("ctrl-\\", CTRL_BACKSLASH),
+];
+
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("ctrl-s", Action::Save),
+    ("ctrl-q", Action::Quit),
+    ("ctrl-c", Action::Cancel),
+    ("ctrl-o", Action::Open),
+    ("ctrl-f", Action::Find),
+    ("ctrl-h", Action::Replace),
+    ("ctrl-g", Action::GotoLine),
+    ("ctrl-z", Action::Undo),
+    ("ctrl-y", Action::Redo),
+    ("ctrl-k", Action::CutToLineEnd),
+    ("ctrl-l", Action::DeleteLine),
+    ("ctrl-d", Action::Duplicate),
+    ("alt-up", Action::MoveLineUp),
+    ("alt-down", Action::MoveLineDown),
+    ("ctrl-u", Action::Paste),
+    ("ctrl-]", Action::MatchingBracket),
+    ("ctrl-n", Action::LineNumbers),
+    ("ctrl-w", Action::Wrap),
+    ("ctrl-b", Action::Whitespace),
+    ("insert", Action::Overwrite),
+    ("ctrl-/", Action::Help),
+    ("f1", Action::Help),
+    ("ctrl-r", Action::RecentFiles),
+    ("ctrl-pagedown", Action::NextBuffer),
+    ("ctrl-pageup", Action::PreviousBuffer),
+    ("up", Action::Up),
+    ("down", Action::Down),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("ctrl-left", Action::WordLeft),
+    ("ctrl-right", Action::WordRight),
+    ("home", Action::LineStart),
+    ("end", Action::LineEnd),
+    ("ctrl-home", Action::FileStart),
+    ("ctrl-end", Action::FileEnd),
+    ("pageup", Action::PageUp),
+    ("pagedown", Action::PageDown),
+    ("shift-up", Action::SelectUp),
+    ("shift-down", Action::SelectDown),
+    ("shift-left", Action::SelectLeft),
+    ("shift-right", Action::SelectRight),
+    ("enter", Action::Newline),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("tab", Action::Tab),
+    ("shift-tab", Action::Dedent),
+    ("ctrl-\\", Action::ToggleComment),
+    ("ctrl-p", Action::Rewrap),
+    ("ctrl-x", Action::Format),
+    ("ctrl-t", Action::Stats),
+    ("ctrl-e", Action::Command),
+];
+
+pub type Keymap = HashMap<u32, Action>;
+
+pub fn default_keymap() -> Keymap {
+    DEFAULT_BINDINGS
+        .iter()
+        .filter_map(|&(name, action)| Some((parse_key(name)?, action)))
+        .collect()
+}
+
+/// The action going by `name` in the config file.
+pub fn parse_action(name: &str) -> Option<Action> {
+    ACTIONS
+        .iter()
+        .find(|(_, action_name, _)| *action_name == name)
+        .map(|&(action, ..)| action)
+}
+
+/// The key written as `name` in the config file, like `ctrl-s`, `pageup` or `x`.
+pub fn parse_key(name: &str) -> Option<u32> {
+    let lowercase = name.to_ascii_lowercase();
+
+    if let Some(&(_, key)) = KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| *key_name == lowercase)
+    {
+        return Some(key);
+    }
+
+    // a single character keeps its case, since Q is a different key from q
+    let mut chars = name.chars();
+    match (lowercase.strip_prefix("ctrl-"), chars.next(), chars.next()) {
+        (Some(letter), ..) => match letter.as_bytes() {
+            [c @ b'a'..=b'z'] => Some((c & 0x1f) as u32),
+            _ => None,
+        },
+        (None, Some(c), None) => Some(c as u32),
+        _ => None,
+    }
+}
+
+/// How `key` is written, the way `parse_key` reads it.
+fn key_name(key: u32) -> String {
+    if let Some((name, _)) = KEY_NAMES.iter().find(|&&(_, named)| named == key) {
+        return String::from(*name);
+    }
+
+    match key {
+        0x01..=0x1a => format!("ctrl-{}", (b'a' + key as u8 - 1) as char),
+        _ => char::from_u32(key).map_or_else(String::new, String::from),
+    }
+}
+
+/// The help screen's lines, as the keys bound to each action and what the action does.
+pub fn help_lines(keymap: &Keymap) -> Vec<(String, &'static str)> {
+    let mut lines = Vec::new();
+
+    for &(action, _, description) in ACTIONS {
+        let Some(description) = description else {
+            continue;
+        };
+
+        let mut keys: Vec<String> = keymap
+            .iter()
+            .filter(|&(_, &bound)| bound == action)
+            .map(|(&key, _)| key_name(key))
+            .collect();
+        if keys.is_empty() {
+            continue;
+        }
+
+        keys.sort();
+        lines.push((keys.join(", "), description));
+    }
+
+    lines
+}
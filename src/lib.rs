@@ -0,0 +1,14 @@
+//! The editor itself, for the binary to run in a terminal or for another program to embed.
+
+pub mod editor;
+pub mod encoding;
+pub mod input;
+pub mod keymap;
+pub mod lines;
+pub mod render;
+pub mod search;
+pub mod syntax;
+pub mod terminal;
+pub mod theme;
+
+pub use editor::Editor;
@@ -0,0 +1,607 @@
+//! The list of lines a buffer is made of, quick to edit anywhere in a large file, and
+//! holding only part of a file in memory while the rest can be read again from it.
+
+use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
+use std::ops::{Index, IndexMut, Range};
+
+/// How many lines a chunk holds before it's split in two.
+const MAX_CHUNK_LINES: usize = 1024;
+/// How few lines a chunk can be left with by removals before it's merged into a neighbour.
+const MIN_CHUNK_LINES: usize = MAX_CHUNK_LINES / 4;
+
+/// Reads the given number of lines back from a range of bytes of the file they came from.
+pub type Source<T> = Box<dyn Fn(Range<u64>, usize) -> Vec<T>>;
+
+struct Chunk<T> {
+    /// The lines, unless they were forgotten and are read again from the file when needed.
+    lines: OnceCell<Vec<T>>,
+    len: usize,
+    /// Where the lines are in the file, as long as they're still what was read from there.
+    /// A chunk that's changed has none and stays in memory.
+    stored: Option<Range<u64>>,
+}
+
+impl<T> Chunk<T> {
+    fn new(lines: Vec<T>, stored: Option<Range<u64>>) -> Chunk<T> {
+        Chunk {
+            len: lines.len(),
+            lines: OnceCell::from(lines),
+            stored,
+        }
+    }
+}
+
+/// Lines kept in chunks, so inserting or removing one only shifts the lines of its chunk
+/// instead of every line after it in the file. Where each later chunk starts still has to be
+/// moved along, which is one number per thousand or so lines.
+///
+/// Chunks read from a file and left unchanged can be forgotten, and are read again from
+/// their place in the file by `source` the next time one of their lines is looked at.
+pub struct Lines<T> {
+    chunks: Vec<Chunk<T>>,
+    /// The index of the first line of each chunk, to find a line's chunk by binary search.
+    starts: Vec<usize>,
+    len: usize,
+    source: Option<Source<T>>,
+    /// The last forgotten chunk read by `peek`, kept apart from the rest so going through the
+    /// whole file doesn't bring all of it back into memory.
+    peeked: RefCell<Option<(usize, Vec<T>)>>,
+}
+
+impl<T> Default for Lines<T> {
+    fn default() -> Lines<T> {
+        Lines {
+            chunks: Vec::new(),
+            starts: Vec::new(),
+            len: 0,
+            source: None,
+            peeked: RefCell::new(None),
+        }
+    }
+}
+
+impl<T> Lines<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        let (chunk, offset) = self.locate(index);
+        Some(&self.chunk_lines(chunk)[offset])
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        let (chunk, offset) = self.locate(index);
+        Some(&mut self.chunk_lines_mut(chunk)[offset])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.chunks.len()).flat_map(|chunk| self.chunk_lines(chunk).iter())
+    }
+
+    /// Inserts `line` so it ends up at `index`, like `Vec::insert`.
+    pub fn insert(&mut self, index: usize, line: T) {
+        assert!(index <= self.len, "line {} out of {}", index, self.len);
+
+        if self.chunks.is_empty() {
+            self.chunks.push(Chunk::new(Vec::new(), None));
+            self.starts.push(0);
+        }
+
+        // the end of the list belongs to the last chunk, not a new empty one
+        let (chunk, offset) = if index == self.len {
+            let last = self.chunks.len() - 1;
+            (last, self.chunks[last].len)
+        } else {
+            self.locate(index)
+        };
+
+        self.chunk_lines_mut(chunk).insert(offset, line);
+        self.chunks[chunk].len += 1;
+        self.len += 1;
+
+        if self.chunks[chunk].len > MAX_CHUNK_LINES {
+            let second_half = self.chunk_lines_mut(chunk).split_off(MAX_CHUNK_LINES / 2);
+            self.chunks[chunk].len = MAX_CHUNK_LINES / 2;
+            self.chunks.insert(chunk + 1, Chunk::new(second_half, None));
+            self.starts.insert(chunk + 1, 0);
+        }
+
+        self.update_starts(chunk);
+    }
+
+    /// Removes and returns the line at `index`, like `Vec::remove`.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "line {} out of {}", index, self.len);
+
+        let (chunk, offset) = self.locate(index);
+        let line = self.chunk_lines_mut(chunk).remove(offset);
+        self.chunks[chunk].len -= 1;
+        self.len -= 1;
+
+        let mut changed = chunk;
+        if self.chunks[chunk].len == 0 {
+            self.chunks.remove(chunk);
+            self.starts.remove(chunk);
+        } else if self.chunks[chunk].len < MIN_CHUNK_LINES {
+            // a run of small chunks would make finding a line slow again, so it joins the
+            // smaller of its neighbours if they fit in one
+            let neighbour = [chunk.checked_sub(1), Some(chunk + 1)]
+                .into_iter()
+                .flatten()
+                .filter(|&other| other < self.chunks.len())
+                .min_by_key(|&other| self.chunks[other].len);
+            if let Some(other) = neighbour
+                .filter(|&other| self.chunks[other].len + self.chunks[chunk].len <= MAX_CHUNK_LINES)
+            {
+                let first = chunk.min(other);
+                let second = std::mem::take(self.chunk_lines_mut(first + 1));
+                self.chunks.remove(first + 1);
+                self.starts.remove(first + 1);
+                self.chunks[first].len += second.len();
+                self.chunk_lines_mut(first).extend(second);
+                changed = first;
+            }
+        }
+
+        self.update_starts(changed);
+        line
+    }
+
+    /// Adds `line`, read from `range` of the file, at the end. It goes in the last chunk if
+    /// that was read from right before it, so the chunk can still be read back in one go.
+    pub fn push_stored(&mut self, line: T, range: Range<u64>) {
+        *self.peeked.get_mut() = None;
+
+        let last = self.chunks.len().checked_sub(1);
+        let extends = last.filter(|&last| {
+            let chunk = &self.chunks[last];
+            chunk.len < MAX_CHUNK_LINES
+                && chunk
+                    .stored
+                    .as_ref()
+                    .is_some_and(|stored| stored.end == range.start)
+        });
+
+        match extends {
+            Some(last) => {
+                self.chunk_lines(last);
+                let chunk = &mut self.chunks[last];
+                chunk.lines.get_mut().unwrap().push(line);
+                chunk.len += 1;
+                chunk.stored.as_mut().unwrap().end = range.end;
+            }
+            None => {
+                self.chunks.push(Chunk::new(vec![line], Some(range)));
+                self.starts.push(self.len);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Sets what reads forgotten chunks back from the file.
+    pub fn set_source(&mut self, source: Source<T>) {
+        self.source = Some(source);
+    }
+
+    /// Reads forgotten chunks with `source` from now on, which reads them differently, and
+    /// changes the lines in memory with `change` to match. Without a `source` every chunk is
+    /// read first with the old one, to be kept in memory.
+    pub fn reread(&mut self, source: Option<Source<T>>, mut change: impl FnMut(&mut T)) {
+        if source.is_none() {
+            self.read_all();
+        }
+        *self.peeked.get_mut() = None;
+        for chunk in &mut self.chunks {
+            if let Some(lines) = chunk.lines.get_mut() {
+                lines.iter_mut().for_each(&mut change);
+            }
+        }
+        self.source = source;
+    }
+
+    /// Forgets the unchanged chunks that have no line in any of the `keep` ranges, to be read
+    /// again from the file when they're needed.
+    pub fn forget(&mut self, keep: &[Range<usize>]) {
+        if self.source.is_none() {
+            return;
+        }
+
+        for (chunk, &start) in self.chunks.iter_mut().zip(&self.starts) {
+            let lines = start..start + chunk.len;
+            let kept = keep
+                .iter()
+                .any(|keep| keep.start < lines.end && lines.start < keep.end);
+            if chunk.stored.is_some() && !kept {
+                chunk.lines.take();
+            }
+        }
+    }
+
+    /// Whether any chunk is forgotten, and would have to be read again from the file.
+    pub fn has_forgotten(&self) -> bool {
+        self.chunks.iter().any(|chunk| chunk.lines.get().is_none())
+    }
+
+    /// Reads every forgotten chunk back into memory, for when the file they're read from is
+    /// about to be written over.
+    pub fn read_all(&self) {
+        for chunk in 0..self.chunks.len() {
+            self.chunk_lines(chunk);
+        }
+    }
+
+    /// Holds on to the `lines` of a forgotten `chunk`, read for the moment with `chunks`, from
+    /// now on only in memory.
+    pub fn keep(&mut self, chunk: usize, lines: Vec<T>) {
+        *self.peeked.get_mut() = None;
+        let chunk = &mut self.chunks[chunk];
+        chunk.lines = OnceCell::from(lines);
+        chunk.stored = None;
+    }
+
+    /// Moves the chunks over to a file that was just written from them, at the `stored` range
+    /// each ended up at, or none for those that wouldn't be read back the same from there.
+    /// Those are read from the old file first if they're forgotten and weren't kept, as
+    /// they're only in memory from now on.
+    pub fn store(&mut self, stored: Vec<Option<Range<u64>>>, source: Option<Source<T>>) {
+        *self.peeked.get_mut() = None;
+
+        for (chunk, stored) in stored.into_iter().enumerate() {
+            if stored.is_none() || source.is_none() {
+                self.chunk_lines(chunk);
+                self.chunks[chunk].stored = None;
+            } else {
+                self.chunks[chunk].stored = stored;
+            }
+        }
+        self.source = source;
+    }
+
+    /// The lines of `chunk`, read back from the file if they were forgotten.
+    fn chunk_lines(&self, chunk: usize) -> &Vec<T> {
+        let chunk = &self.chunks[chunk];
+        chunk.lines.get_or_init(|| self.read(chunk))
+    }
+
+    /// The lines of `chunk` to be changed, which from then on are only kept in memory.
+    fn chunk_lines_mut(&mut self, chunk: usize) -> &mut Vec<T> {
+        *self.peeked.get_mut() = None;
+        self.chunk_lines(chunk);
+        let chunk = &mut self.chunks[chunk];
+        chunk.stored = None;
+        chunk.lines.get_mut().unwrap()
+    }
+
+    fn read(&self, chunk: &Chunk<T>) -> Vec<T> {
+        let source = self
+            .source
+            .as_ref()
+            .expect("forgotten lines without a file");
+        let stored = chunk
+            .stored
+            .clone()
+            .expect("forgotten lines that were changed");
+        source(stored, chunk.len)
+    }
+
+    /// The chunk holding the line at `index`, and where the line is in it.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let chunk = self.starts.partition_point(|&start| start <= index) - 1;
+        (chunk, index - self.starts[chunk])
+    }
+
+    /// Recomputes where each chunk starts, from `chunk` onwards.
+    fn update_starts(&mut self, chunk: usize) {
+        *self.peeked.get_mut() = None;
+        if let Some(first) = self.starts.first_mut() {
+            *first = 0;
+        }
+        for i in chunk.max(1)..self.chunks.len() {
+            self.starts[i] = self.starts[i - 1] + self.chunks[i - 1].len;
+        }
+    }
+}
+
+impl<T: Clone> Lines<T> {
+    /// The line at `index`, without keeping its chunk in memory if it was forgotten, for going
+    /// through more of the file than is worth holding at once.
+    pub fn peek(&self, index: usize) -> Option<Cow<'_, T>> {
+        if index >= self.len {
+            return None;
+        }
+        let (chunk, offset) = self.locate(index);
+        if let Some(lines) = self.chunks[chunk].lines.get() {
+            return Some(Cow::Borrowed(&lines[offset]));
+        }
+
+        let mut peeked = self.peeked.borrow_mut();
+        if peeked.as_ref().is_none_or(|(peeked, _)| *peeked != chunk) {
+            *peeked = Some((chunk, self.read(&self.chunks[chunk])));
+        }
+        let (_, lines) = peeked.as_ref().unwrap();
+        Some(Cow::Owned(lines[offset].clone()))
+    }
+
+    /// Each chunk's lines in turn, the forgotten ones read for the moment and not kept.
+    pub fn chunks(&self) -> impl Iterator<Item = Cow<'_, [T]>> {
+        self.chunks.iter().map(|chunk| match chunk.lines.get() {
+            Some(lines) => Cow::Borrowed(lines.as_slice()),
+            None => Cow::Owned(self.read(chunk)),
+        })
+    }
+}
+
+impl Lines<String> {
+    pub fn join(&self, separator: &str) -> String {
+        let mut text = String::new();
+        for (i, line) in (0..self.len).filter_map(|i| self.peek(i)).enumerate() {
+            if i > 0 {
+                text.push_str(separator);
+            }
+            text.push_str(&line);
+        }
+        text
+    }
+}
+
+impl<T> Index<usize> for Lines<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(line) => line,
+            None => panic!("line {} out of {}", index, self.len),
+        }
+    }
+}
+
+impl<T> IndexMut<usize> for Lines<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len;
+        match self.get_mut(index) {
+            Some(line) => line,
+            None => panic!("line {} out of {}", index, len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Lines numbered from 0 to `len`, added at the end like a file being read.
+    fn numbered(len: usize) -> Lines<usize> {
+        let mut lines = Lines::default();
+        for i in 0..len {
+            lines.insert(i, i);
+        }
+        lines
+    }
+
+    /// Lines numbered from 0 to `len` as if read from a file where each takes ten bytes, and
+    /// how many times chunks were read back from there.
+    fn stored(len: usize) -> (Lines<usize>, Rc<Cell<usize>>) {
+        let mut lines = Lines::default();
+        for i in 0..len {
+            let at = i as u64 * 10;
+            lines.push_stored(i, at..at + 10);
+        }
+
+        let reads = Rc::new(Cell::new(0));
+        let counted = reads.clone();
+        lines.set_source(Box::new(move |range, len| {
+            counted.set(counted.get() + 1);
+            (range.start as usize / 10..).take(len).collect()
+        }));
+        (lines, reads)
+    }
+
+    fn in_memory(lines: &Lines<usize>) -> usize {
+        let chunks = lines.chunks.iter();
+        chunks.filter(|chunk| chunk.lines.get().is_some()).count()
+    }
+
+    /// Checks `lines` holds what `expected` does, and that its chunks add up.
+    fn check(lines: &Lines<usize>, expected: &[usize]) {
+        assert_eq!(lines.len(), expected.len());
+        assert!(lines.iter().eq(expected.iter()));
+        for (i, line) in expected.iter().enumerate() {
+            assert_eq!(lines[i], *line, "line {}", i);
+        }
+        assert_eq!(lines.get(expected.len()), None);
+
+        let mut start = 0;
+        for (i, chunk) in lines.chunks.iter().enumerate() {
+            assert_eq!(lines.starts[i], start);
+            assert!(chunk.len > 0 && chunk.len <= MAX_CHUNK_LINES);
+            if let Some(lines) = chunk.lines.get() {
+                assert_eq!(lines.len(), chunk.len);
+            }
+            start += chunk.len;
+        }
+    }
+
+    #[test]
+    fn full_chunk_is_split_in_two() {
+        let mut lines = numbered(MAX_CHUNK_LINES);
+        assert_eq!(lines.chunks.len(), 1);
+
+        lines.insert(MAX_CHUNK_LINES, MAX_CHUNK_LINES);
+        let lens: Vec<usize> = lines.chunks.iter().map(|chunk| chunk.len).collect();
+        assert_eq!(lens, [MAX_CHUNK_LINES / 2, MAX_CHUNK_LINES / 2 + 1]);
+        check(&lines, &(0..=MAX_CHUNK_LINES).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn edits_at_chunk_boundaries() {
+        let mut lines = numbered(MAX_CHUNK_LINES * 3);
+        let mut expected: Vec<usize> = (0..MAX_CHUNK_LINES * 3).collect();
+
+        for chunk in 1..lines.chunks.len() {
+            let start = lines.starts[chunk];
+            for at in [start - 1, start, start + 1] {
+                lines.insert(at, 10_000 + at);
+                expected.insert(at, 10_000 + at);
+                check(&lines, &expected);
+            }
+            for at in [start + 1, start, start - 1] {
+                assert_eq!(lines.remove(at), expected.remove(at));
+                check(&lines, &expected);
+            }
+        }
+
+        // the very ends
+        lines.insert(0, 20_000);
+        expected.insert(0, 20_000);
+        lines.insert(expected.len(), 20_001);
+        expected.push(20_001);
+        check(&lines, &expected);
+        assert_eq!(lines.remove(expected.len() - 1), expected.pop().unwrap());
+        assert_eq!(lines.remove(0), expected.remove(0));
+        check(&lines, &expected);
+    }
+
+    #[test]
+    fn emptied_chunks_go_away() {
+        let mut lines = numbered(MAX_CHUNK_LINES * 2);
+        let mut expected: Vec<usize> = (0..MAX_CHUNK_LINES * 2).collect();
+        let chunks = lines.chunks.len();
+
+        // the second chunk, one line at a time from its start
+        let start = lines.starts[1];
+        let len = lines.chunks[1].len;
+        for _ in 0..len {
+            assert_eq!(lines.remove(start), expected.remove(start));
+            check(&lines, &expected);
+        }
+        assert!(lines.chunks.len() < chunks);
+
+        while !expected.is_empty() {
+            assert_eq!(lines.remove(0), expected.remove(0));
+            check(&lines, &expected);
+        }
+        assert!(lines.chunks.is_empty());
+        lines.insert(0, 1);
+        check(&lines, &[1]);
+    }
+
+    #[test]
+    fn small_chunks_are_merged() {
+        let mut lines = numbered(MAX_CHUNK_LINES * 4);
+        let mut expected: Vec<usize> = (0..MAX_CHUNK_LINES * 4).collect();
+
+        // every chunk shrinks in turn, a line from the middle of each
+        let mut step = 0;
+        while expected.len() > MIN_CHUNK_LINES {
+            let chunk = step % lines.chunks.len();
+            step += 1;
+            let at = lines.starts[chunk] + lines.chunks[chunk].len / 2;
+            assert_eq!(lines.remove(at), expected.remove(at));
+            check(&lines, &expected);
+
+            // a small chunk is only left where it doesn't fit into either neighbour, so no two
+            // are ever next to each other
+            assert!(lines
+                .chunks
+                .windows(2)
+                .all(|pair| pair.iter().any(|chunk| chunk.len >= MIN_CHUNK_LINES)));
+        }
+        assert_eq!(lines.chunks.len(), 1);
+    }
+
+    #[test]
+    fn forgotten_chunks_are_read_again() {
+        let (mut lines, reads) = stored(MAX_CHUNK_LINES * 3 - 10);
+        let expected: Vec<usize> = (0..MAX_CHUNK_LINES * 3 - 10).collect();
+        assert_eq!(lines.chunks.len(), 3);
+
+        lines.forget(&[0..10, 20..30]);
+        assert_eq!(in_memory(&lines), 1);
+        assert!(lines.has_forgotten());
+
+        assert_eq!(lines[MAX_CHUNK_LINES * 2 + 5], MAX_CHUNK_LINES * 2 + 5);
+        assert_eq!(lines[MAX_CHUNK_LINES * 2 + 6], MAX_CHUNK_LINES * 2 + 6);
+        assert_eq!(reads.get(), 1);
+        assert_eq!(in_memory(&lines), 2);
+        check(&lines, &expected);
+    }
+
+    #[test]
+    fn changed_chunks_stay_in_memory() {
+        let (mut lines, reads) = stored(MAX_CHUNK_LINES * 3);
+        let mut expected: Vec<usize> = (0..MAX_CHUNK_LINES * 3).collect();
+
+        lines[MAX_CHUNK_LINES + 1] = 0;
+        expected[MAX_CHUNK_LINES + 1] = 0;
+        lines.forget(&[]);
+        assert_eq!(in_memory(&lines), 1);
+        check(&lines, &expected);
+        assert_eq!(reads.get(), 2);
+
+        // a line read after a changed chunk doesn't go in with it
+        lines.remove(expected.len() - 1);
+        expected.pop();
+        let at = expected.len() as u64 * 10;
+        lines.push_stored(expected.len(), at..at + 10);
+        expected.push(expected.len());
+        assert_eq!(lines.chunks.len(), 4);
+        check(&lines, &expected);
+    }
+
+    #[test]
+    fn peeking_doesnt_keep_chunks() {
+        let (mut lines, reads) = stored(MAX_CHUNK_LINES * 3);
+        lines.forget(&[]);
+
+        for i in 0..lines.len() {
+            assert_eq!(*lines.peek(i).unwrap(), i);
+        }
+        assert_eq!(lines.peek(lines.len()), None);
+        assert_eq!(in_memory(&lines), 0);
+        assert_eq!(reads.get(), 3);
+
+        let chunks: Vec<usize> = lines.chunks().map(|chunk| chunk.len()).collect();
+        assert_eq!(chunks, [MAX_CHUNK_LINES; 3]);
+        assert_eq!(in_memory(&lines), 0);
+    }
+
+    #[test]
+    fn stored_chunks_move_to_the_file_written() {
+        let (mut lines, _) = stored(MAX_CHUNK_LINES * 3);
+        let expected: Vec<usize> = (0..MAX_CHUNK_LINES * 3).collect();
+        lines.forget(&[]);
+
+        // the middle chunk can't be read back from the new file, so it's read from the old one
+        // before that's let go
+        let stored = vec![Some(0..10_240), None, Some(10_240..20_480)];
+        lines.store(
+            stored,
+            Some(Box::new(|range, len| {
+                let first = match range.start {
+                    0 => 0,
+                    _ => MAX_CHUNK_LINES * 2,
+                };
+                (first..).take(len).collect()
+            })),
+        );
+        assert_eq!(in_memory(&lines), 1);
+        lines.forget(&[]);
+        assert_eq!(in_memory(&lines), 1);
+        check(&lines, &expected);
+    }
+}
@@ -0,0 +1,1070 @@
+//! Drawing the editor to the screen.
+
+use std::io::Write;
+use std::time::Duration;
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::editor::{Buffer, Editor, Mode, LOAD_AHEAD_LINES, VERSION};
+use crate::keymap::help_lines;
+use crate::search::Search;
+use crate::syntax::{highlight_line, Highlight, HighlightOpen};
+use crate::theme::{Color, ColorDepth, Theme};
+
+const STATUS_MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Editor {
+    pub fn scroll(&mut self) {
+        let buffer = self.current_buffer();
+        self.load_lines(buffer.offset_y.max(buffer.cursor_y) + self.rows + LOAD_AHEAD_LINES);
+
+        let (rows, columns, tab_stop) = (self.rows, text_columns(self), self.tab_stop);
+        let wrap = self.wrap;
+        let buffer = self.current_buffer_mut();
+        buffer.forget_far_lines(rows);
+
+        buffer.render_x = match buffer.content.get(buffer.cursor_y) {
+            Some(line) => cursor_x_to_render_x(line, buffer.cursor_x, tab_stop),
+            None => 0,
+        };
+
+        if wrap {
+            // wrapped lines never scroll sideways, and the screen rows they take decide how
+            // far down the cursor can be
+            buffer.offset_x = 0;
+            if buffer.cursor_y < buffer.offset_y {
+                buffer.offset_y = buffer.cursor_y;
+            }
+
+            let (cursor_row, _) = wrapped_cursor(buffer, columns, tab_stop);
+            let mut used = cursor_row + 1;
+            let mut y = buffer.cursor_y;
+            while y > buffer.offset_y {
+                let above = line_rows(buffer, y - 1, columns, tab_stop);
+                if used + above > rows {
+                    break;
+                }
+                used += above;
+                y -= 1;
+            }
+            buffer.offset_y = y;
+            return;
+        }
+
+        if buffer.cursor_y >= rows + buffer.offset_y {
+            buffer.offset_y = buffer.cursor_y - rows + 1;
+        } else if buffer.cursor_y < buffer.offset_y {
+            buffer.offset_y = buffer.cursor_y;
+        }
+
+        if buffer.render_x >= columns + buffer.offset_x {
+            buffer.offset_x = buffer.render_x - columns + 1;
+        } else if buffer.render_x < buffer.offset_x {
+            buffer.offset_x = buffer.render_x;
+        }
+    }
+
+    /// Moves the cursor to the character clicked on, unless the click was outside the text.
+    pub fn click(&mut self, row: usize, column: usize) {
+        let gutter_width = gutter_width(self);
+        if row >= self.rows || column < gutter_width {
+            return;
+        }
+
+        let screen_row = screen_rows(self).swap_remove(row);
+        let tab_stop = self.tab_stop;
+        let buffer = self.current_buffer_mut();
+
+        match screen_row {
+            Some(ScreenRow { y, skip, .. }) => {
+                buffer.cursor_y = y;
+                let line = &buffer.content[y];
+                buffer.cursor_x =
+                    render_x_to_cursor_x(line, skip + column - gutter_width, tab_stop);
+            }
+            // past the end of the file, which is as far as the cursor goes
+            None => {
+                buffer.cursor_y = buffer.content.len().saturating_sub(1);
+                buffer.cursor_x = buffer
+                    .content
+                    .get(buffer.cursor_y)
+                    .map_or(0, |line| line.chars().count());
+            }
+        }
+    }
+
+    /// Moves the cursor a screen row up or down, which is within the same line when it's
+    /// wrapped onto several rows.
+    pub fn move_by_screen_row(&mut self, down: bool) {
+        let (columns, tab_stop) = (text_columns(self), self.tab_stop);
+        let buffer = self.current_buffer_mut();
+        let (row, column) = wrapped_cursor(buffer, columns, tab_stop);
+
+        let (y, row) = if down {
+            if row + 1 < line_rows(buffer, buffer.cursor_y, columns, tab_stop) {
+                (buffer.cursor_y, row + 1)
+            } else if buffer.cursor_y + 1 < buffer.content.len() {
+                (buffer.cursor_y + 1, 0)
+            } else {
+                return;
+            }
+        } else if row > 0 {
+            (buffer.cursor_y, row - 1)
+        } else if buffer.cursor_y > 0 {
+            let y = buffer.cursor_y - 1;
+            (y, line_rows(buffer, y, columns, tab_stop) - 1)
+        } else {
+            return;
+        };
+
+        buffer.cursor_y = y;
+        buffer.cursor_x = match buffer.content.get(y) {
+            Some(line) => {
+                // stay on the row even if it's shorter than the one the cursor was on
+                let points = wrap_points(&render_line(line, tab_stop), columns);
+                let mut render_x = points[row] + column;
+                if let Some(&next) = points.get(row + 1) {
+                    render_x = render_x.min(next - 1);
+                }
+                render_x_to_cursor_x(line, render_x, tab_stop)
+            }
+            None => 0,
+        };
+    }
+
+    /// Scrolls the view by `lines`, up when negative, taking the cursor along only when it
+    /// would otherwise go off screen.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let rows = self.rows;
+        let buffer = self.current_buffer_mut();
+
+        buffer.offset_y = buffer
+            .offset_y
+            .saturating_add_signed(lines)
+            .min(buffer.content.len().saturating_sub(1));
+        buffer.cursor_y = buffer
+            .cursor_y
+            .max(buffer.offset_y)
+            .min(buffer.offset_y + rows - 1)
+            .min(buffer.content.len().saturating_sub(1));
+    }
+}
+
+/// Draws the screen, writing only the rows that look different from when it was last drawn.
+pub fn refresh_screen(editor: &mut Editor) {
+    let mut rows = draw_content(editor);
+    let mut status_bar = String::new();
+    draw_status_bar(editor, &mut status_bar);
+    rows.push(status_bar);
+    let mut message_bar = String::new();
+    draw_message_bar(editor, &mut message_bar);
+    rows.push(message_bar);
+
+    let mut builder = String::new();
+
+    builder.push_str("\x1b[?25l"); // hide the cursor while the screen is repainted
+    for (i, row) in rows.iter().enumerate() {
+        if editor.drawn_rows.get(i) != Some(row) {
+            builder.push_str(format!("\x1b[{};1H", i + 1).as_str());
+            builder.push_str(row.as_str());
+        }
+    }
+    if editor.show_help {
+        draw_help(editor, &mut builder);
+    }
+    draw_recent_files(editor, &mut builder);
+    draw_cursor(editor, &mut builder);
+    builder.push_str("\x1b[?25h"); // show the cursor again
+
+    write_output(editor, builder.as_bytes());
+
+    // a box drawn over the text hides rows that then have to be drawn again once it's gone
+    let covered = editor.show_help || editor.recent_files.is_some();
+    editor.drawn_rows = if covered { Vec::new() } else { rows };
+}
+
+/// Draws the key bindings in a box over the text.
+fn draw_help(editor: &Editor, builder: &mut String) {
+    let bindings = help_lines(&editor.keymap);
+    let key_width = bindings
+        .iter()
+        .map(|(keys, _)| keys.width())
+        .max()
+        .unwrap_or(0);
+    let mut lines = vec![String::from("Keys"), String::new()];
+    for (keys, description) in &bindings {
+        lines.push(format!("{:<2$}  {}", keys, description, key_width));
+    }
+    lines.push(String::new());
+    lines.push(String::from("Press any key to close"));
+
+    draw_box(editor, builder, &lines, None);
+}
+
+/// Draws the recently opened files in a box, with the one picked shown in reverse.
+fn draw_recent_files(editor: &Editor, builder: &mut String) {
+    let Some(recent) = &editor.recent_files else {
+        return;
+    };
+
+    let mut lines = vec![String::from("Recent files"), String::new()];
+    lines.extend(recent.paths.iter().cloned());
+    lines.push(String::new());
+    lines.push(String::from("Enter to open, any other key to close"));
+
+    draw_box(editor, builder, &lines, Some(recent.selected + 2));
+}
+
+/// Draws `lines` in a box in the middle of the screen, over whatever is there. The line at
+/// `highlighted`, if any, is drawn in reverse.
+fn draw_box(editor: &Editor, builder: &mut String, lines: &[String], highlighted: Option<usize>) {
+    let inner_width = lines.iter().map(|line| line.width()).max().unwrap_or(0) + 2;
+    let width = (inner_width + 2).min(editor.columns);
+    let height = (lines.len() + 2).min(editor.rows);
+    let top = (editor.rows - height) / 2 + 1;
+    let left = (editor.columns - width) / 2 + 1;
+
+    let border = "─".repeat(width.saturating_sub(2));
+    for row in 0..height {
+        builder.push_str(format!("\x1b[{};{}H", top + row, left).as_str());
+
+        let text = if row == 0 {
+            format!("┌{}┐", border)
+        } else if row == height - 1 {
+            format!("└{}┘", border)
+        } else {
+            let line = format!(" {}", lines[row - 1]);
+            let inner = take_columns(&line, 0, width.saturating_sub(2));
+            let padding = width.saturating_sub(2) - inner.width();
+            match highlighted == Some(row - 1) {
+                true => format!("│\x1b[7m{}{}\x1b[0m│", inner, " ".repeat(padding)),
+                false => format!("│{}{}│", inner, " ".repeat(padding)),
+            }
+        };
+        builder.push_str(text.as_str());
+    }
+}
+
+fn draw_status_bar(editor: &Editor, builder: &mut String) {
+    let banner = format!(" Ari Code's Editor - v{} - Rust Edition - ", VERSION);
+    let mut file_status = String::new();
+    if editor.buffers.len() > 1 {
+        let position = format!("[{}/{}] ", editor.active_buffer + 1, editor.buffers.len());
+        file_status.push_str(position.as_str());
+    }
+    file_status.push_str(get_file_name(editor));
+
+    let buffer = editor.current_buffer();
+    if buffer.dirty {
+        file_status.push_str(" [+]");
+    }
+    if buffer.read_only {
+        file_status.push_str(" [RO]");
+    }
+    // a file still being read has more lines than are counted so far
+    let more = if buffer.is_loaded() { "" } else { "+" };
+    let mode = match editor.mode {
+        Mode::Normal => "NOR",
+        Mode::Insert if editor.overwrite => "OVR",
+        Mode::Insert => "INS",
+    };
+    let info_message = format!(
+        "{}  {} | {}  Line {}/{}{}, Col {} ",
+        mode,
+        buffer.encoding.name(),
+        buffer.line_ending.name(),
+        buffer.cursor_y + 1,
+        buffer.content.len().max(1),
+        more,
+        buffer.render_x + 1
+    );
+
+    // on narrow terminals the banner is cut short first, then the info, and the file's name
+    // only once it's all there's room for. A cut banner keeps a space on each side of the name.
+    let columns = editor.columns;
+    let banner_room = columns.saturating_sub(file_status.width() + info_message.width() + 2);
+    let status_message = match banner_room {
+        0 => take_columns(&format!(" {} ", file_status), 0, columns),
+        _ => take_columns(banner.trim_end(), 0, banner_room) + " " + file_status.as_str(),
+    };
+    let status_len = status_message.width();
+    let info_message = take_columns(&info_message, 0, columns - status_len);
+    let info_len = info_message.width();
+
+    builder.push_str(format!("\x1b[{}m", editor.theme.status_bar_sgr(editor.color_depth)).as_str());
+    builder.push_str(status_message.as_str());
+    builder.push_str(
+        " ".repeat(editor.columns.saturating_sub(status_len + info_len))
+            .as_str(),
+    );
+    builder.push_str(info_message.as_str());
+    builder.push_str("\x1b[0m");
+}
+
+/// Draws just the message bar, for showing progress in the middle of something that keeps
+/// the screen from being refreshed.
+pub fn refresh_message_bar(editor: &mut Editor) {
+    let mut message_bar = String::new();
+    draw_message_bar(editor, &mut message_bar);
+    let bytes = format!("\x1b[{};1H{}", editor.rows + 2, message_bar).into_bytes();
+    write_output(editor, &bytes);
+
+    if let Some(drawn) = editor.drawn_rows.get_mut(editor.rows + 1) {
+        *drawn = message_bar;
+    }
+}
+
+fn write_output(editor: &mut Editor, bytes: &[u8]) {
+    let output = &mut editor.output;
+
+    output
+        .write_all(bytes)
+        .and_then(|_| output.flush())
+        .expect("Error writing to output stream");
+}
+
+fn draw_message_bar(editor: &Editor, builder: &mut String) {
+    builder.push_str("\x1b[K");
+
+    if editor.status_message_time.elapsed() < STATUS_MESSAGE_TIMEOUT {
+        let message = take_columns(&editor.status_message, 0, editor.columns);
+        builder.push_str(message.as_str());
+    }
+}
+
+fn get_file_name(editor: &Editor) -> &str {
+    let buffer = editor.current_buffer();
+
+    if buffer.filename.is_empty() {
+        "New File"
+    } else {
+        &buffer.filename
+    }
+}
+
+/// A row of the screen showing part of a line: which line, the screen column of the
+/// rendered line it starts at, and whether it's where the line starts.
+struct ScreenRow {
+    y: usize,
+    skip: usize,
+    first: bool,
+}
+
+/// What each row of the text area shows, `None` for the rows past the end of the file.
+fn screen_rows(editor: &Editor) -> Vec<Option<ScreenRow>> {
+    let buffer = editor.current_buffer();
+    let columns = text_columns(editor);
+    let mut rows = Vec::with_capacity(editor.rows);
+
+    for y in buffer.offset_y.. {
+        if rows.len() >= editor.rows {
+            break;
+        }
+
+        if y >= buffer.content.len() {
+            rows.push(None);
+        } else if editor.wrap {
+            for (i, skip) in wrap_points(&render_line(&buffer.content[y], editor.tab_stop), columns)
+                .into_iter()
+                .enumerate()
+            {
+                rows.push(Some(ScreenRow {
+                    y,
+                    skip,
+                    first: i == 0,
+                }));
+            }
+        } else {
+            rows.push(Some(ScreenRow {
+                y,
+                skip: buffer.offset_x,
+                first: true,
+            }));
+        }
+    }
+
+    rows.truncate(editor.rows);
+    rows
+}
+
+/// The screen columns of the rendered line where each of the rows it wraps onto starts.
+fn wrap_points(render: &str, width: usize) -> Vec<usize> {
+    let mut points = vec![0];
+    let mut column = 0;
+
+    for c in render.chars() {
+        let c_width = char_width(c);
+        // a wide character that doesn't fit goes whole onto the next row
+        if column + c_width > points[points.len() - 1] + width {
+            points.push(column);
+        }
+        column += c_width;
+    }
+
+    points
+}
+
+/// How many screen rows line `y` takes when wrapped.
+fn line_rows(buffer: &Buffer, y: usize, width: usize, tab_stop: usize) -> usize {
+    match buffer.content.get(y) {
+        Some(line) => wrap_points(&render_line(line, tab_stop), width).len(),
+        None => 1,
+    }
+}
+
+/// Which of its line's wrapped rows the cursor is on, and the column within that row.
+fn wrapped_cursor(buffer: &Buffer, width: usize, tab_stop: usize) -> (usize, usize) {
+    let Some(line) = buffer.content.get(buffer.cursor_y) else {
+        return (0, 0);
+    };
+
+    let points = wrap_points(&render_line(line, tab_stop), width);
+    let row = points
+        .iter()
+        .rposition(|&point| point <= buffer.render_x)
+        .unwrap_or(0);
+    let column = buffer.render_x - points[row];
+
+    // at the very end of a line that fills its last row, the cursor is on a row of its own
+    match column >= width {
+        true => (row + 1, 0),
+        false => (row, column),
+    }
+}
+
+fn draw_content(editor: &Editor) -> Vec<String> {
+    let buffer = editor.current_buffer();
+    let gutter_width = gutter_width(editor);
+    let columns = text_columns(editor);
+    // only a match that could be on screen is worth looking for
+    let matching_bracket = buffer.matching_bracket(editor.rows);
+    // the search shows its own matches, and with a selection it's the selection that matters
+    let word_search = match editor.searching || buffer.selection().is_some() {
+        true => None,
+        false => buffer.word_at_cursor().map(|word| Search::word(&word)),
+    };
+    // started with nothing to edit, the editor says what it is until something's typed
+    let welcome = buffer.content.is_empty() && buffer.filename.is_empty();
+
+    let mut rows = Vec::with_capacity(editor.rows);
+
+    for (screen_i, screen_row) in screen_rows(editor).into_iter().enumerate() {
+        let mut row = String::new();
+        let Some(ScreenRow {
+            y: file_i,
+            skip,
+            first,
+        }) = screen_row
+        else {
+            row.push_str(" ".repeat(gutter_width).as_str());
+            row.push('~');
+            if welcome && screen_i == editor.rows / 3 {
+                let message = format!("Ari Code's Editor -- version {}", VERSION);
+                let message: String = message.chars().take(columns.saturating_sub(1)).collect();
+                let padding = columns.saturating_sub(message.len()) / 2;
+                row.push_str(" ".repeat(padding.saturating_sub(1)).as_str());
+                row.push_str(message.as_str());
+            }
+            row.push_str("\x1b[K");
+            rows.push(row);
+            continue;
+        };
+
+        if gutter_width > 0 {
+            let number = match first {
+                true => format!("{:>1$} ", file_i + 1, gutter_width - 1),
+                false => " ".repeat(gutter_width),
+            };
+            row.push_str(number.as_str());
+        }
+
+        let render = &render_line(&buffer.content[file_i], editor.tab_stop);
+        let selected = selected_range(editor, file_i);
+        let matching_x = matching_bracket
+            .filter(|&(_, y)| y == file_i)
+            .map(|(x, _)| render_index(&buffer.content[file_i], x, editor.tab_stop));
+
+        // tabs are spaces once rendered, so trailing whitespace is just the trailing spaces
+        let trailing = render.trim_end_matches(' ').chars().count();
+        let has_trailing = trailing < render.chars().count();
+        let found = match editor.searching {
+            true => match_ranges(editor, &editor.search, file_i),
+            false => Vec::new(),
+        };
+        let word_found = match &word_search {
+            Some(search) => match_ranges(editor, search, file_i),
+            None => Vec::new(),
+        };
+
+        if buffer.syntax.is_none()
+            && !editor.show_whitespace
+            && selected.is_none()
+            && matching_x.is_none()
+            && !has_trailing
+            && found.is_empty()
+            && word_found.is_empty()
+        {
+            row.push_str(take_columns(render, skip, columns).as_str());
+        } else {
+            let mut highlight = match buffer.syntax {
+                Some(syntax) => {
+                    let open = match file_i {
+                        0 => HighlightOpen::Closed,
+                        _ => buffer.highlight_open[file_i - 1],
+                    };
+                    highlight_line(render, syntax, open).0
+                }
+                None => vec![Highlight::Normal; render.chars().count()],
+            };
+
+            // tabs are spaces once rendered, so every space drawn is one or the other
+            let shown;
+            let mut render = render;
+            if editor.show_whitespace {
+                for (hl, c) in highlight.iter_mut().zip(render.chars()) {
+                    if c == ' ' {
+                        *hl = Highlight::Whitespace;
+                    }
+                }
+                shown = render_whitespace(&buffer.content[file_i], editor.tab_stop);
+                render = &shown;
+            }
+            if has_trailing {
+                highlight[trailing..].fill(Highlight::TrailingWhitespace);
+            }
+            for (start, end) in word_found {
+                let end = end.min(highlight.len());
+                highlight[start.min(end)..end].fill(Highlight::WordMatch);
+            }
+            for (start, end) in found {
+                let end = end.min(highlight.len());
+                highlight[start.min(end)..end].fill(Highlight::SearchMatch);
+            }
+            if let Some((start, end)) = selected {
+                let end = end.min(highlight.len());
+                highlight[start.min(end)..end].fill(Highlight::Selection);
+            }
+            if let Some(x) = matching_x.filter(|&x| x < highlight.len()) {
+                highlight[x] = Highlight::MatchingBracket;
+            }
+            draw_highlighted(
+                &mut row,
+                &editor.theme,
+                editor.color_depth,
+                render,
+                &highlight,
+                skip,
+                columns,
+            );
+        }
+
+        row.push_str("\x1b[K");
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// The SGR parameters `highlight` is drawn with.
+fn highlight_color(theme: &Theme, depth: ColorDepth, highlight: Highlight) -> String {
+    let color = match highlight {
+        Highlight::Normal => Color::Default,
+        Highlight::Keyword => theme.keyword,
+        Highlight::Type => theme.type_name,
+        Highlight::String => theme.string,
+        Highlight::Comment => theme.comment,
+        Highlight::Number => theme.number,
+        Highlight::Selection if theme.selection == Color::Default => return String::from("7"),
+        Highlight::Selection => return theme.selection.sgr(true, depth),
+        Highlight::MatchingBracket => return String::from("4"),
+        Highlight::TrailingWhitespace => return String::from("41"),
+        Highlight::SearchMatch => return String::from("43"),
+        Highlight::WordMatch => return String::from("100"),
+        Highlight::Whitespace => return String::from("2"),
+    };
+    color.sgr(false, depth)
+}
+
+/// Like `take_columns`, but colors each character by its highlight as it goes. The color
+/// is reset at the end so the rest of the line isn't tinted.
+fn draw_highlighted(
+    builder: &mut String,
+    theme: &Theme,
+    depth: ColorDepth,
+    text: &str,
+    highlight: &[Highlight],
+    skip: usize,
+    width: usize,
+) {
+    let mut column = 0;
+    let mut current = Highlight::Normal;
+
+    for (c, &hl) in text.chars().zip(highlight) {
+        let c_width = char_width(c);
+        let c_end = column + c_width;
+
+        if c_end > skip + width {
+            break;
+        }
+
+        if c_end > skip && hl != current {
+            // reset first, so leaving the reversed colors of a selection goes back to normal
+            builder.push_str(format!("\x1b[0;{}m", highlight_color(theme, depth, hl)).as_str());
+            current = hl;
+        }
+
+        if column >= skip {
+            builder.push(c);
+        } else if c_end > skip {
+            builder.push_str(" ".repeat(c_end - skip).as_str());
+        }
+
+        column = c_end;
+    }
+
+    builder.push_str("\x1b[0m");
+}
+
+/// The characters of the rendered line `y` that are selected, as a range of indices into it.
+fn selected_range(editor: &Editor, y: usize) -> Option<(usize, usize)> {
+    let buffer = editor.current_buffer();
+    let ((start_x, start_y), (end_x, end_y)) = buffer.selection()?;
+    if y < start_y || y > end_y {
+        return None;
+    }
+
+    let line = &buffer.content[y];
+    let start = match y == start_y {
+        true => render_index(line, start_x, editor.tab_stop),
+        false => 0,
+    };
+    let end = match y == end_y {
+        true => render_index(line, end_x, editor.tab_stop),
+        false => render_line(line, editor.tab_stop).chars().count(),
+    };
+
+    Some((start, end.max(start)))
+}
+
+/// The matches of `search` on line `y`, as ranges of indices into the rendered line.
+fn match_ranges(editor: &Editor, search: &Search, y: usize) -> Vec<(usize, usize)> {
+    let line = &editor.current_buffer().content[y];
+    search
+        .matches(line)
+        .map(|(start, end)| {
+            let start_x = line[..start].chars().count();
+            let end_x = start_x + line[start..end].chars().count();
+            (
+                render_index(line, start_x, editor.tab_stop),
+                render_index(line, end_x, editor.tab_stop),
+            )
+        })
+        .collect()
+}
+
+/// How many characters of the rendered line the first `cursor_x` characters of `line` become
+/// once tabs are expanded.
+fn render_index(line: &str, cursor_x: usize, tab_stop: usize) -> usize {
+    let (mut index, mut render_x) = (0, 0);
+
+    for c in line.chars().take(cursor_x) {
+        if c == '\t' {
+            let spaces = tab_stop - (render_x % tab_stop);
+            index += spaces;
+            render_x += spaces;
+        } else {
+            index += 1;
+            render_x += char_width(c);
+        }
+    }
+
+    index
+}
+
+/// Cuts the part of `text` that is displayed from screen column `skip` on, at most
+/// `width` columns wide. Wide characters split by either edge are replaced by spaces.
+fn take_columns(text: &str, skip: usize, width: usize) -> String {
+    let mut visible = String::new();
+    let mut column = 0;
+
+    for c in text.chars() {
+        let c_width = char_width(c);
+        let c_end = column + c_width;
+
+        if c_end > skip + width {
+            visible.push_str(
+                " ".repeat((skip + width).saturating_sub(column.max(skip)))
+                    .as_str(),
+            );
+            break;
+        }
+
+        if column >= skip {
+            visible.push(c);
+        } else if c_end > skip {
+            visible.push_str(" ".repeat(c_end - skip).as_str());
+        }
+
+        column = c_end;
+    }
+
+    visible
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// How many columns the line numbers take, including the separator after them.
+fn gutter_width(editor: &Editor) -> usize {
+    if !editor.show_line_numbers {
+        return 0;
+    }
+
+    let lines = editor.current_buffer().content.len().max(1);
+    lines.to_string().len() + 1
+}
+
+/// How many columns are left for the text once the gutter is drawn.
+fn text_columns(editor: &Editor) -> usize {
+    editor.columns.saturating_sub(gutter_width(editor)).max(1)
+}
+
+fn draw_cursor(editor: &Editor, builder: &mut String) {
+    let buffer = editor.current_buffer();
+
+    let (row, column) = match editor.wrap {
+        true => {
+            let (cursor_row, column) =
+                wrapped_cursor(buffer, text_columns(editor), editor.tab_stop);
+            let rows_above: usize = (buffer.offset_y..buffer.cursor_y)
+                .map(|y| line_rows(buffer, y, text_columns(editor), editor.tab_stop))
+                .sum();
+            (rows_above + cursor_row, column)
+        }
+        false => (
+            buffer.cursor_y - buffer.offset_y,
+            buffer.render_x - buffer.offset_x,
+        ),
+    };
+
+    builder.push_str(format!("\x1b[{};{}H", row + 1, column + gutter_width(editor) + 1).as_str());
+    // set cursor position
+
+    // the steady shapes, not the blinking ones
+    if editor.cursor_shape {
+        let shape = match editor.mode == Mode::Normal || editor.overwrite {
+            true => 2,
+            false => 6,
+        };
+        builder.push_str(format!("\x1b[{} q", shape).as_str());
+    }
+}
+
+pub fn render_line(line: &str, tab_stop: usize) -> String {
+    render_line_as(line, tab_stop, false)
+}
+
+/// Like `render_line`, but with spaces drawn as `·` and each tab as `→` and the spaces after
+/// it. Both take a single column, so the line lines up the same either way.
+fn render_whitespace(line: &str, tab_stop: usize) -> String {
+    render_line_as(line, tab_stop, true)
+}
+
+fn render_line_as(line: &str, tab_stop: usize, show_whitespace: bool) -> String {
+    let mut render = String::with_capacity(line.len());
+    let mut render_x = 0;
+
+    for c in line.chars() {
+        if c == '\t' {
+            // pad with spaces up to the next tab stop
+            let spaces = tab_stop - (render_x % tab_stop);
+            if show_whitespace {
+                render.push('→');
+                render.push_str(" ".repeat(spaces - 1).as_str());
+            } else {
+                render.push_str(" ".repeat(spaces).as_str());
+            }
+            render_x += spaces;
+        } else if c == ' ' && show_whitespace {
+            render.push('·');
+            render_x += 1;
+        } else {
+            render.push(c);
+            render_x += char_width(c);
+        }
+    }
+
+    render
+}
+
+/// The cursor column of the character drawn at `render_x`, or the end of the line if the
+/// line is shorter.
+fn render_x_to_cursor_x(line: &str, render_x: usize, tab_stop: usize) -> usize {
+    let mut current_x = 0;
+
+    for (cursor_x, c) in line.chars().enumerate() {
+        current_x += match c {
+            '\t' => tab_stop - (current_x % tab_stop),
+            _ => char_width(c),
+        };
+        if current_x > render_x {
+            return cursor_x;
+        }
+    }
+
+    line.chars().count()
+}
+
+pub fn cursor_x_to_render_x(line: &str, cursor_x: usize, tab_stop: usize) -> usize {
+    let mut render_x = 0;
+
+    for c in line.chars().take(cursor_x) {
+        if c == '\t' {
+            render_x += tab_stop - (render_x % tab_stop);
+        } else {
+            render_x += char_width(c);
+        }
+    }
+
+    render_x
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::editor::Buffer;
+
+    /// Where a test's editor draws, kept so the test can look at it afterwards.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The screen as the terminal would show it, without colors: each row's text with the
+    /// spaces at its end left off, and the row and column the cursor is on. `bytes` is what
+    /// was written to draw it, escape sequences and all.
+    struct Screen {
+        rows: Vec<String>,
+        cursor: (usize, usize),
+        bytes: String,
+    }
+
+    /// An editor with `rows` rows of text by `columns` columns, showing `lines`.
+    fn editor_with(rows: usize, columns: usize, lines: &[&str]) -> Editor {
+        let mut editor = Editor::new(rows, columns);
+        let mut buffer = Buffer::default();
+        for (y, line) in lines.iter().enumerate() {
+            buffer.content.insert(y, String::from(*line));
+        }
+        editor.buffers.push(buffer);
+        editor
+    }
+
+    /// Draws the whole screen through the editor's output and plays back what was written.
+    fn snapshot(editor: &mut Editor) -> Screen {
+        let output = Output::default();
+        editor.output = Box::new(output.clone());
+        editor.drawn_rows.clear();
+        editor.scroll();
+        refresh_screen(editor);
+
+        let bytes = output.0.borrow();
+        let text = String::from_utf8_lossy(&bytes);
+        let mut grid = vec![vec![' '; editor.columns]; editor.rows + 2];
+        let (mut row, mut column) = (0, 0);
+
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                if let Some(cell) = grid.get_mut(row).and_then(|cells| cells.get_mut(column)) {
+                    *cell = c;
+                }
+                column += 1;
+                continue;
+            }
+
+            // only moving the cursor changes where the text goes
+            chars.next();
+            let mut parameters = String::new();
+            let command = loop {
+                match chars.next() {
+                    Some(c) if c.is_ascii_digit() || c == ';' || c == '?' => parameters.push(c),
+                    command => break command,
+                }
+            };
+            if command == Some('H') {
+                let (r, c) = parameters.split_once(';').unwrap_or((&parameters, "1"));
+                row = r.parse::<usize>().unwrap_or(1) - 1;
+                column = c.parse::<usize>().unwrap_or(1) - 1;
+            }
+        }
+
+        Screen {
+            rows: grid
+                .iter()
+                .map(|cells| cells.iter().collect::<String>().trim_end().to_string())
+                .collect(),
+            cursor: (row, column),
+            bytes: text.into_owned(),
+        }
+    }
+
+    #[test]
+    fn empty_buffer() {
+        let mut editor = editor_with(6, 80, &[]);
+        let screen = snapshot(&mut editor);
+
+        let welcome = format!("Ari Code's Editor -- version {}", VERSION);
+        let status_bar = format!(
+            " Ari Code's Editor - v{} - Rust Ed New File INS  UTF-8 | LF  Line 1/1, Col 1",
+            VERSION
+        );
+        assert_eq!(
+            screen.rows,
+            [
+                "~",
+                "~",
+                &format!("~{:>56}", welcome),
+                "~",
+                "~",
+                "~",
+                &status_bar,
+                "",
+            ]
+        );
+        assert_eq!(screen.cursor, (0, 0));
+    }
+
+    #[test]
+    fn status_bar_with_a_message() {
+        let mut editor = editor_with(3, 60, &["hello"]);
+        editor.current_buffer_mut().dirty = true;
+        editor.status_message = String::from("3 bytes written to disk");
+        editor.status_message_time = Instant::now();
+        let screen = snapshot(&mut editor);
+
+        assert_eq!(
+            screen.rows,
+            [
+                "hello",
+                "~",
+                "~",
+                " Ari Code's E New File [+] INS  UTF-8 | LF  Line 1/1, Col 1",
+                "3 bytes written to disk",
+            ]
+        );
+        // the status bar in reverse video, and the word under the cursor marked
+        assert_eq!(
+            screen.bytes,
+            concat!(
+                "\x1b[?25l",
+                "\x1b[1;1H\x1b[0;100mhello\x1b[0m\x1b[K",
+                "\x1b[2;1H~\x1b[K",
+                "\x1b[3;1H~\x1b[K",
+                "\x1b[4;1H\x1b[7m Ari Code's E New File [+] INS  UTF-8 | LF  Line 1/1, Col 1 \x1b[0m",
+                "\x1b[5;1H\x1b[K3 bytes written to disk",
+                "\x1b[1;1H\x1b[?25h",
+            )
+        );
+    }
+
+    #[test]
+    fn scrolled_view() {
+        let lines: Vec<String> = (1..=20)
+            .map(|y| format!("{} {:02}", "abcdefghij".repeat(3), y))
+            .collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(4, 20, &lines);
+        let buffer = editor.current_buffer_mut();
+        (buffer.cursor_x, buffer.cursor_y) = (33, 12);
+        let screen = snapshot(&mut editor);
+
+        assert_eq!(
+            &screen.rows[..4],
+            [
+                "efghijabcdefghij 10",
+                "efghijabcdefghij 11",
+                "efghijabcdefghij 12",
+                "efghijabcdefghij 13",
+            ]
+        );
+        let buffer = editor.current_buffer();
+        assert_eq!((buffer.offset_x, buffer.offset_y), (14, 9));
+        assert_eq!(screen.cursor, (3, 19));
+        assert_eq!(
+            screen.bytes,
+            concat!(
+                "\x1b[?25l",
+                "\x1b[1;1Hefghijabcdefghij 10\x1b[K",
+                "\x1b[2;1Hefghijabcdefghij 11\x1b[K",
+                "\x1b[3;1Hefghijabcdefghij 12\x1b[K",
+                "\x1b[4;1Hefghijabcdefghij 13\x1b[K",
+                "\x1b[5;1H\x1b[7m New File INS  UTF-8\x1b[0m",
+                "\x1b[6;1H\x1b[K",
+                "\x1b[4;20H\x1b[?25h",
+            )
+        );
+    }
+
+    #[test]
+    fn three_lines_with_the_cursor_at_line_1_column_2() {
+        let mut editor = editor_with(3, 60, &["one", "two", "three"]);
+        editor.current_buffer_mut().cursor_x = 1;
+        let screen = snapshot(&mut editor);
+
+        assert_eq!(
+            screen.rows,
+            [
+                "one",
+                "two",
+                "three",
+                " Ari Code's Edito New File INS  UTF-8 | LF  Line 1/3, Col 2",
+                "",
+            ]
+        );
+        // the terminal's rows and columns count from 1
+        assert_eq!(screen.cursor, (0, 1));
+        assert_eq!(
+            screen.bytes,
+            concat!(
+                "\x1b[?25l",
+                "\x1b[1;1H\x1b[0;100mone\x1b[0m\x1b[K",
+                "\x1b[2;1Htwo\x1b[K",
+                "\x1b[3;1Hthree\x1b[K",
+                "\x1b[4;1H\x1b[7m Ari Code's Edito New File INS  UTF-8 | LF  Line 1/3, Col 2 \x1b[0m",
+                "\x1b[5;1H\x1b[K",
+                "\x1b[1;2H\x1b[?25h",
+            )
+        );
+    }
+
+    #[test]
+    fn narrow_status_bar_keeps_the_file_name() {
+        let mut editor = Editor::new(10, 20);
+        editor.buffers.push(Buffer::default());
+
+        let mut status_bar = String::new();
+        draw_status_bar(&editor, &mut status_bar);
+
+        assert!(
+            status_bar.contains(" New File INS  UTF-8"),
+            "{:?}",
+            status_bar
+        );
+    }
+}
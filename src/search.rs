@@ -0,0 +1,127 @@
+//! What a search looks for, either the text typed or a regular expression.
+
+use regex::{Regex, RegexBuilder};
+
+use crate::editor::is_word_char;
+
+/// A search query and the options it's matched with, compiled to a regex either way so
+/// plain text and patterns are found the same way.
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    /// Whether the query is a regular expression rather than text to find as it is.
+    pub regex: bool,
+    pub ignore_case: bool,
+    /// Whether only matches with no word characters right before or after them count.
+    pub whole_word: bool,
+    pattern: Option<Regex>,
+    /// Why the query couldn't be compiled, when it's a regular expression that isn't valid.
+    pub error: Option<String>,
+}
+
+impl Search {
+    /// A search for `query` as plain text, matching case.
+    pub fn new(query: &str) -> Search {
+        let mut search = Search::default();
+        search.set_query(query);
+        search
+    }
+
+    /// A search for `word` on its own, not as part of a longer word.
+    pub fn word(word: &str) -> Search {
+        let mut search = Search {
+            whole_word: true,
+            ..Search::default()
+        };
+        search.set_query(word);
+        search
+    }
+
+    pub fn set_query(&mut self, query: &str) {
+        self.query = String::from(query);
+        self.compile();
+    }
+
+    /// Builds the pattern again, for when the query or one of the options changed.
+    pub fn compile(&mut self) {
+        let source = match self.regex {
+            true => self.query.clone(),
+            false => regex::escape(&self.query),
+        };
+
+        (self.pattern, self.error) = match RegexBuilder::new(&source)
+            .case_insensitive(self.ignore_case)
+            .build()
+        {
+            Ok(pattern) if !self.query.is_empty() => (Some(pattern), None),
+            Ok(_) => (None, None),
+            // the whole error is several lines pointing at the mistake, so only the last is kept
+            Err(err) => {
+                let message = err.to_string();
+                let reason = message.lines().last().unwrap_or_default();
+                (
+                    None,
+                    Some(String::from(reason.trim_start_matches("error: "))),
+                )
+            }
+        };
+    }
+
+    /// The options that are on, as they're shown in the search prompt.
+    pub fn flags(&self) -> String {
+        let mut flags = String::new();
+        if self.regex {
+            flags.push_str("[re]");
+        }
+        if self.ignore_case {
+            flags.push_str("[i]");
+        }
+        if self.whole_word {
+            flags.push_str("[w]");
+        }
+        flags
+    }
+
+    /// Where the matches in `line` start and end, as byte indices.
+    pub fn matches<'a>(&'a self, line: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let (found_start, found_end) = self.find_from(line, start)?;
+            // an empty match would be found again at the same place
+            start = match found_end > found_start {
+                true => found_end,
+                false => next_char(line, found_end)?,
+            };
+            Some((found_start, found_end))
+        })
+    }
+
+    /// The first match in `line` starting at byte `start` or later.
+    pub fn find_from(&self, line: &str, start: usize) -> Option<(usize, usize)> {
+        let pattern = self.pattern.as_ref()?;
+        let mut start = start;
+
+        loop {
+            let found = pattern.find_at(line, start)?;
+            if !self.whole_word || is_whole_word(line, found.start(), found.end()) {
+                return Some((found.start(), found.end()));
+            }
+            // a match that's part of a longer word may overlap one that isn't
+            start = next_char(line, found.start())?;
+        }
+    }
+}
+
+/// Whether the text of `line` from byte `start` to `end` has no word characters on either
+/// side of it, so it isn't part of a longer word.
+fn is_whole_word(line: &str, start: usize, end: usize) -> bool {
+    let before = line[..start].chars().next_back();
+    let after = line[end..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+/// Where the character after the one at byte `index` of `line` starts, if it isn't the end.
+fn next_char(line: &str, index: usize) -> Option<usize> {
+    let c = line[index..].chars().next()?;
+    Some(index + c.len_utf8())
+}
@@ -0,0 +1,330 @@
+//! Syntax highlighting, by language.
+
+use std::path::Path;
+
+use crate::editor::Buffer;
+use crate::lines::Lines;
+use crate::render::render_line;
+
+/// How to highlight the files of one language.
+pub struct Syntax {
+    extensions: &'static [&'static str],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    line_comment: &'static str,
+    block_comment: (&'static str, &'static str),
+    /// Whether a single character between `'`s, like `'"'`, is a literal rather than a
+    /// quote that's used for something else.
+    char_literals: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Highlight {
+    Normal,
+    Keyword,
+    Type,
+    String,
+    Comment,
+    Number,
+    /// Selected text, drawn over whatever it would be colored as.
+    Selection,
+    /// The bracket matching the one under the cursor.
+    MatchingBracket,
+    /// Spaces and tabs at the end of a line.
+    TrailingWhitespace,
+    /// Text matching the search being typed.
+    SearchMatch,
+    /// Another place the word under the cursor is used.
+    WordMatch,
+    /// A space or tab drawn as a symbol, while whitespace is shown.
+    Whitespace,
+}
+
+/// What is still open at the end of a line and carries over to the next one.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum HighlightOpen {
+    #[default]
+    Closed,
+    /// Block comments nest in Rust, so this counts how deep we are.
+    Comment(usize),
+    String,
+}
+
+static SYNTAXES: &[Syntax] = &[Syntax {
+    extensions: &["rs"],
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+        "i64", "i128", "isize", "f32", "f64", "String", "Vec", "Option", "Result", "Box",
+    ],
+    line_comment: "//",
+    block_comment: ("/*", "*/"),
+    char_literals: true,
+}];
+
+/// The line comments of files that aren't highlighted, by extension. Anything not here or in
+/// `SYNTAXES` gets `#`, which most config files and scripts use.
+const LINE_COMMENTS: &[(&str, &str)] = &[
+    ("c", "//"),
+    ("h", "//"),
+    ("cpp", "//"),
+    ("go", "//"),
+    ("java", "//"),
+    ("js", "//"),
+    ("ts", "//"),
+    ("lua", "--"),
+    ("sql", "--"),
+    ("hs", "--"),
+    ("vim", "\""),
+    ("el", ";"),
+    ("lisp", ";"),
+    ("ini", ";"),
+];
+
+/// What starts a line comment in the file at `file_path`, chosen by its extension.
+pub fn line_comment(file_path: &str) -> &'static str {
+    if let Some(syntax) = detect_syntax(file_path) {
+        return syntax.line_comment;
+    }
+
+    let extension = Path::new(file_path).extension().and_then(|e| e.to_str());
+    LINE_COMMENTS
+        .iter()
+        .find(|&&(comment_extension, _)| Some(comment_extension) == extension)
+        .map_or("#", |&(_, comment)| comment)
+}
+
+pub fn detect_syntax(file_path: &str) -> Option<&'static Syntax> {
+    let extension = Path::new(file_path).extension()?.to_str()?;
+
+    SYNTAXES
+        .iter()
+        .find(|syntax| syntax.extensions.contains(&extension))
+}
+
+/// Works out the highlight of each character of a rendered line, given what was left open
+/// by the line before it. Also returns what this line leaves open for the next one.
+pub fn highlight_line(
+    render: &str,
+    syntax: &Syntax,
+    open: HighlightOpen,
+) -> (Vec<Highlight>, HighlightOpen) {
+    let chars: Vec<char> = render.chars().collect();
+    let mut highlight = vec![Highlight::Normal; chars.len()];
+    let comment: Vec<char> = syntax.line_comment.chars().collect();
+    let comment_start: Vec<char> = syntax.block_comment.0.chars().collect();
+    let comment_end: Vec<char> = syntax.block_comment.1.chars().collect();
+
+    let mut i = 0;
+    let mut open = open;
+    let mut previous_separator = true;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let previous = if i > 0 {
+            highlight[i - 1]
+        } else {
+            Highlight::Normal
+        };
+
+        match open {
+            HighlightOpen::Comment(depth) => {
+                if chars[i..].starts_with(&comment_end) {
+                    highlight[i..i + comment_end.len()].fill(Highlight::Comment);
+                    i += comment_end.len();
+                    open = match depth {
+                        1 => HighlightOpen::Closed,
+                        _ => HighlightOpen::Comment(depth - 1),
+                    };
+                    previous_separator = true;
+                } else if chars[i..].starts_with(&comment_start) {
+                    highlight[i..i + comment_start.len()].fill(Highlight::Comment);
+                    i += comment_start.len();
+                    open = HighlightOpen::Comment(depth + 1);
+                } else {
+                    highlight[i] = Highlight::Comment;
+                    i += 1;
+                }
+                continue;
+            }
+            HighlightOpen::String => {
+                // an escaped quote doesn't close the string
+                highlight[i] = Highlight::String;
+                if c == '\\' && i + 1 < chars.len() {
+                    highlight[i + 1] = Highlight::String;
+                    i += 2;
+                    continue;
+                }
+                if c == '"' {
+                    open = HighlightOpen::Closed;
+                    previous_separator = true;
+                }
+                i += 1;
+                continue;
+            }
+            HighlightOpen::Closed => {}
+        }
+
+        if !comment.is_empty() && chars[i..].starts_with(&comment) {
+            highlight[i..].fill(Highlight::Comment);
+            break;
+        }
+
+        if !comment_start.is_empty() && chars[i..].starts_with(&comment_start) {
+            highlight[i..i + comment_start.len()].fill(Highlight::Comment);
+            i += comment_start.len();
+            open = HighlightOpen::Comment(1);
+            continue;
+        }
+
+        // a quote in a character literal doesn't start a string
+        if let Some(len) = char_literal_len(&chars[i..]).filter(|_| syntax.char_literals) {
+            highlight[i..i + len].fill(Highlight::String);
+            i += len;
+            previous_separator = true;
+            continue;
+        }
+
+        if c == '"' {
+            highlight[i] = Highlight::String;
+            i += 1;
+            open = HighlightOpen::String;
+            continue;
+        }
+
+        if (c.is_ascii_digit() && (previous_separator || previous == Highlight::Number))
+            || (previous == Highlight::Number
+                && (c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+        {
+            highlight[i] = Highlight::Number;
+            previous_separator = false;
+            i += 1;
+            continue;
+        }
+
+        if previous_separator {
+            let words = syntax
+                .keywords
+                .iter()
+                .map(|word| (word, Highlight::Keyword))
+                .chain(syntax.types.iter().map(|word| (word, Highlight::Type)));
+
+            let mut matched = false;
+            for (word, kind) in words {
+                let word: Vec<char> = word.chars().collect();
+                let end = i + word.len();
+                if chars[i..].starts_with(&word) && chars.get(end).is_none_or(|&c| is_separator(c))
+                {
+                    highlight[i..end].fill(kind);
+                    i = end;
+                    matched = true;
+                    break;
+                }
+            }
+
+            if matched {
+                previous_separator = false;
+                continue;
+            }
+        }
+
+        previous_separator = is_separator(c);
+        i += 1;
+    }
+
+    (highlight, open)
+}
+
+/// Recomputes what each line leaves open, starting at line `from` and going down only as
+/// long as that keeps changing.
+pub fn update_highlight(buffer: &mut Buffer, from: usize, tab_stop: usize) {
+    let Some(syntax) = buffer.syntax else {
+        return;
+    };
+
+    let mut open = match from {
+        0 => HighlightOpen::Closed,
+        _ => buffer.highlight_open[from - 1],
+    };
+
+    for y in from..buffer.content.len() {
+        // a comment opened or closed can reach far down a file not all kept in memory
+        let render = render_line(&buffer.content.peek(y).unwrap(), tab_stop);
+        let (_, next) = highlight_line(&render, syntax, open);
+        if buffer.highlight_open[y] == next {
+            break;
+        }
+        buffer.highlight_open[y] = next;
+        open = next;
+    }
+}
+
+/// Works out what every line leaves open from the start, for a buffer whose syntax changed.
+/// Lines aren't kept for a buffer that isn't highlighted.
+pub fn highlight_all(buffer: &mut Buffer, tab_stop: usize) {
+    buffer.highlight_open = Lines::default();
+    let Some(syntax) = buffer.syntax else {
+        return;
+    };
+
+    let mut open = HighlightOpen::Closed;
+    for y in 0..buffer.content.len() {
+        let line = buffer.content.peek(y).unwrap();
+        open = highlight_line(&render_line(&line, tab_stop), syntax, open).1;
+        buffer.highlight_open.insert(y, open);
+    }
+}
+
+/// How long the character literal at the start of `chars` is, like `'x'`, `'\''` or
+/// `'\u{1F600}'`. A quote that doesn't close right after one character, like a Rust lifetime,
+/// isn't one.
+fn char_literal_len(chars: &[char]) -> Option<usize> {
+    if chars.first() != Some(&'\'') {
+        return None;
+    }
+
+    match chars.get(1)? {
+        '\\' => {
+            // the escaped character, then whatever a longer escape like \u{..} goes on with
+            let end = chars.iter().skip(3).take(10).position(|&c| c == '\'')?;
+            Some(3 + end + 1)
+        }
+        '\'' => None,
+        _ => (chars.get(2) == Some(&'\'')).then_some(3),
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || (c.is_ascii_punctuation() && c != '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_literals_dont_open_strings() {
+        let syntax = detect_syntax("main.rs").unwrap();
+        for line in [r#"if c == '"' {"#, r"if c == '\'' {", r"let c = '\u{22}';"] {
+            let (highlight, open) = highlight_line(line, syntax, HighlightOpen::Closed);
+            assert!(open == HighlightOpen::Closed, "{}", line);
+            assert!(highlight[line.find('\'').unwrap()] == Highlight::String);
+            assert!(
+                highlight[highlight.len() - 1] == Highlight::Normal,
+                "{}",
+                line
+            );
+        }
+
+        // a lifetime isn't a literal
+        let (highlight, open) =
+            highlight_line("fn f<'a>(s: &'a str)", syntax, HighlightOpen::Closed);
+        assert!(open == HighlightOpen::Closed);
+        assert!(!highlight.contains(&Highlight::String));
+    }
+}
@@ -0,0 +1,476 @@
+//! Raw mode, the window size and the lowest level of reading and writing the terminal.
+
+use std::fs::File;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::editor::Editor;
+
+#[cfg(unix)]
+const SYSTEM_OUT_FD: libc::c_int = 0;
+
+const DEFAULT_WINDOW_SIZE: (usize, usize) = (80, 24);
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+pub static WINDOW_SIZE_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Input read from the terminal a chunk at a time and handed out a byte at a time, so a
+/// paste or an escape sequence takes one read instead of one per byte.
+pub struct InputBuffer {
+    bytes: [u8; INPUT_BUFFER_SIZE],
+    start: usize,
+    end: usize,
+    /// The screen row and column, counted from 0, of the last mouse event read.
+    pub mouse: (usize, usize),
+}
+
+impl Default for InputBuffer {
+    fn default() -> InputBuffer {
+        InputBuffer::new()
+    }
+}
+
+impl InputBuffer {
+    pub fn new() -> InputBuffer {
+        InputBuffer {
+            bytes: [0; INPUT_BUFFER_SIZE],
+            start: 0,
+            end: 0,
+            mouse: (0, 0),
+        }
+    }
+
+    /// Whether some of what was read is still to be handed out, so more keys are there to
+    /// handle without waiting for the terminal.
+    pub fn has_unread(&self) -> bool {
+        self.start < self.end
+    }
+}
+
+/// The parts of the terminal handling that differ between platforms.
+trait Terminal {
+    /// Whatever is needed to put the terminal back the way it was found.
+    type RestoreHandle: Copy + Send + Sync + 'static;
+
+    fn enable_raw_mode() -> io::Result<Self::RestoreHandle>;
+    fn disable_raw_mode(handle: &Self::RestoreHandle);
+    /// The size as `(columns, rows)`, if the platform can tell without asking the terminal.
+    fn get_window_size() -> io::Result<(usize, usize)>;
+    /// Waits a short while for input and reads as much of it as fits in `buffer`, returning
+    /// how many bytes were read. Nothing coming in is not an error and reads 0 bytes.
+    fn read_input(buffer: &mut [u8]) -> usize;
+    /// Points stdin and stdout at the terminal itself, for when they were redirected, and
+    /// hands back the original stdout.
+    fn attach_to_terminal() -> io::Result<File>;
+}
+
+#[cfg(unix)]
+type PlatformTerminal = LibcTerminal;
+#[cfg(windows)]
+type PlatformTerminal = WindowsTerminal;
+
+type RestoreHandle = <PlatformTerminal as Terminal>::RestoreHandle;
+
+#[cfg(unix)]
+struct LibcTerminal;
+
+#[cfg(unix)]
+impl Terminal for LibcTerminal {
+    type RestoreHandle = libc::termios;
+
+    fn enable_raw_mode() -> io::Result<libc::termios> {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            let rc: libc::c_int = libc::tcgetattr(SYSTEM_OUT_FD, &mut termios);
+
+            if rc != 0 {
+                let err = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("There was a problem calling tcgetattr: {}", err),
+                ));
+            }
+
+            let original_attributes = termios;
+
+            termios.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+            termios.c_iflag &= !(libc::IXON | libc::ICRNL);
+            termios.c_oflag &= !(libc::OPOST);
+
+            termios.c_cc[libc::VMIN] = 0;
+            termios.c_cc[libc::VTIME] = 1;
+
+            if libc::tcsetattr(SYSTEM_OUT_FD, libc::TCSAFLUSH, &termios) != 0 {
+                let err = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("There was a problem calling tcsetattr: {}", err),
+                ));
+            }
+
+            Ok(original_attributes)
+        }
+    }
+
+    fn disable_raw_mode(termios: &libc::termios) {
+        unsafe {
+            libc::tcsetattr(SYSTEM_OUT_FD, libc::TCSAFLUSH, termios);
+        }
+    }
+
+    fn get_window_size() -> io::Result<(usize, usize)> {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        let rc = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+        if rc == -1 || size.ws_col == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((size.ws_col as usize, size.ws_row as usize))
+    }
+
+    fn read_input(buffer: &mut [u8]) -> usize {
+        // VMIN and VTIME make this give up after a tenth of a second
+        let read = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+
+        read.max(0) as usize
+    }
+
+    fn attach_to_terminal() -> io::Result<File> {
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+
+        unsafe {
+            let stdout = libc::dup(libc::STDOUT_FILENO);
+            if stdout == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = File::from_raw_fd(stdout);
+
+            if libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) == -1
+                || libc::dup2(tty.as_raw_fd(), libc::STDOUT_FILENO) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(original)
+        }
+    }
+}
+
+#[cfg(windows)]
+struct WindowsTerminal;
+
+#[cfg(windows)]
+impl Terminal for WindowsTerminal {
+    /// The input and output console modes.
+    type RestoreHandle = (u32, u32);
+
+    fn enable_raw_mode() -> io::Result<(u32, u32)> {
+        use windows_sys::Win32::System::Console::*;
+
+        unsafe {
+            let input = GetStdHandle(STD_INPUT_HANDLE);
+            let output = GetStdHandle(STD_OUTPUT_HANDLE);
+
+            let (mut input_mode, mut output_mode) = (0, 0);
+            if GetConsoleMode(input, &mut input_mode) == 0
+                || GetConsoleMode(output, &mut output_mode) == 0
+            {
+                let err = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("There was a problem calling GetConsoleMode: {}", err),
+                ));
+            }
+
+            // the console then speaks the same escape sequences as a unix terminal
+            let raw_input = (input_mode
+                & !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT))
+                | ENABLE_VIRTUAL_TERMINAL_INPUT;
+            let raw_output = output_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING;
+
+            if SetConsoleMode(input, raw_input) == 0 || SetConsoleMode(output, raw_output) == 0 {
+                let err = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("There was a problem calling SetConsoleMode: {}", err),
+                ));
+            }
+
+            SetConsoleCP(65001); // UTF-8
+            SetConsoleOutputCP(65001);
+
+            Ok((input_mode, output_mode))
+        }
+    }
+
+    fn disable_raw_mode(&(input_mode, output_mode): &(u32, u32)) {
+        use windows_sys::Win32::System::Console::*;
+
+        unsafe {
+            SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), input_mode);
+            SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), output_mode);
+        }
+    }
+
+    fn get_window_size() -> io::Result<(usize, usize)> {
+        use windows_sys::Win32::System::Console::*;
+
+        unsafe {
+            let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+            if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let window = info.srWindow;
+            Ok((
+                (window.Right - window.Left + 1) as usize,
+                (window.Bottom - window.Top + 1) as usize,
+            ))
+        }
+    }
+
+    fn read_input(buffer: &mut [u8]) -> usize {
+        use windows_sys::Win32::Storage::FileSystem::ReadFile;
+        use windows_sys::Win32::System::Console::{GetStdHandle, STD_INPUT_HANDLE};
+        use windows_sys::Win32::System::Threading::WaitForSingleObject;
+
+        unsafe {
+            let input = GetStdHandle(STD_INPUT_HANDLE);
+
+            // there is no VTIME on windows, so wait for input by hand
+            if WaitForSingleObject(input, 100) != 0 {
+                return 0;
+            }
+
+            let mut read = 0;
+            let ok = ReadFile(
+                input,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                &mut read,
+                std::ptr::null_mut(),
+            );
+
+            match ok {
+                0 => 0,
+                _ => read as usize,
+            }
+        }
+    }
+
+    fn attach_to_terminal() -> io::Result<File> {
+        use std::os::windows::io::FromRawHandle;
+        use windows_sys::Win32::Foundation::{GENERIC_READ, GENERIC_WRITE, INVALID_HANDLE_VALUE};
+        use windows_sys::Win32::Storage::FileSystem::{
+            CreateFileW, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        };
+        use windows_sys::Win32::System::Console::*;
+
+        let open = |name: &str| unsafe {
+            let name: Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let handle = CreateFileW(
+                name.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                0,
+                std::ptr::null_mut(),
+            );
+            match handle {
+                INVALID_HANDLE_VALUE => Err(io::Error::last_os_error()),
+                handle => Ok(handle),
+            }
+        };
+
+        let (input, output) = (open("CONIN$")?, open("CONOUT$")?);
+
+        unsafe {
+            let original = GetStdHandle(STD_OUTPUT_HANDLE);
+            if SetStdHandle(STD_INPUT_HANDLE, input) == 0
+                || SetStdHandle(STD_OUTPUT_HANDLE, output) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(File::from_raw_handle(original))
+        }
+    }
+}
+
+/// Keeps the terminal in raw mode for as long as it's alive, and puts it back when dropped.
+pub struct RawMode {
+    handle: RestoreHandle,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let handle = PlatformTerminal::enable_raw_mode()?;
+        write("\x1b[?2004h".as_bytes()); // have pasted text marked as such
+        write("\x1b[?1000h\x1b[?1006h".as_bytes()); // report mouse clicks and the wheel
+
+        // the screen is only cleared this once, each redraw clears the rows it draws
+        write("\x1b[2J".as_bytes());
+
+        // a panic exits without going through the editor, so the hook restores the terminal
+        // before the message is shown
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            write("\x1b[?1006l\x1b[?1000l\x1b[?2004l\x1b[2J\x1b[H".as_bytes());
+            PlatformTerminal::disable_raw_mode(&handle);
+            default_hook(info);
+        }));
+
+        Ok(RawMode { handle })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        write("\x1b[?1006l\x1b[?1000l".as_bytes()); // stop reporting the mouse
+        write("\x1b[?2004l".as_bytes()); // stop marking pasted text
+
+        // when unwinding from a panic the hook already cleared the screen, and clearing it
+        // again would hide the message
+        if !thread::panicking() {
+            write("\x1b[2J".as_bytes()); // clear screen
+            write("\x1b[H".as_bytes()); // set cursor at 0,0
+        }
+
+        PlatformTerminal::disable_raw_mode(&self.handle);
+    }
+}
+
+/// Points stdin and stdout at the terminal, once stdin has been read to the end of the text
+/// piped in, returning where the output was going.
+pub fn attach_to_terminal() -> io::Result<File> {
+    PlatformTerminal::attach_to_terminal()
+}
+
+pub fn enable_raw_mode(editor: &mut Editor) -> io::Result<()> {
+    editor.raw_mode = Some(RawMode::enable()?);
+
+    Ok(())
+}
+
+pub fn set_window_size(editor: &mut Editor) -> io::Result<()> {
+    // when the output isn't a terminal (e.g. it's piped) there is no size to ask for
+    let (columns, rows) = get_window_size(&mut editor.input).unwrap_or(DEFAULT_WINDOW_SIZE);
+    let rows = rows.saturating_sub(2).max(1); // leave room for the status bar and the message line
+
+    // the terminal may have moved or dropped what was on it when it was resized
+    if (columns, rows) != (editor.columns, editor.rows) {
+        editor.drawn_rows.clear();
+    }
+    editor.columns = columns;
+    editor.rows = rows;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" fn on_window_size_changed(_signal: libc::c_int) {
+    WINDOW_SIZE_CHANGED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub fn watch_window_size() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_window_size_changed as extern "C" fn(libc::c_int) as usize;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+    }
+}
+
+/// Windows has no signal for resizes, so there the size is checked every time instead.
+#[cfg(windows)]
+pub fn watch_window_size() {}
+
+pub fn update_window_size(editor: &mut Editor) {
+    if WINDOW_SIZE_CHANGED.swap(false, Ordering::SeqCst) || cfg!(windows) {
+        // keep the old size if the new one can't be read
+        let _ = set_window_size(editor);
+    }
+}
+
+fn get_window_size(input: &mut InputBuffer) -> io::Result<(usize, usize)> {
+    PlatformTerminal::get_window_size().or_else(|_| {
+        // some terminals don't answer, so push the cursor as far to the bottom-right as
+        // it goes and ask the terminal where it ended up
+        write("\x1b[999C\x1b[999B".as_bytes());
+        get_cursor_position(input)
+    })
+}
+
+fn get_cursor_position(input: &mut InputBuffer) -> io::Result<(usize, usize)> {
+    write("\x1b[6n".as_bytes());
+
+    // the answer looks like \x1b[<rows>;<columns>R
+    let mut response = String::new();
+    while let Some(byte) = read_byte(input) {
+        if byte == b'R' || response.len() > 32 {
+            break;
+        }
+        response.push(byte as char);
+    }
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Unable to get terminal size");
+
+    let (rows, columns) = response
+        .strip_prefix("\x1b[")
+        .and_then(|position| position.split_once(';'))
+        .ok_or_else(invalid)?;
+
+    match (rows.parse(), columns.parse()) {
+        (Ok(rows), Ok(columns)) => Ok((columns, rows)),
+        _ => Err(invalid()),
+    }
+}
+
+pub fn read_byte(input: &mut InputBuffer) -> Option<u8> {
+    if input.start == input.end {
+        // only go back to the terminal once everything read before is used up
+        input.start = 0;
+        input.end = PlatformTerminal::read_input(&mut input.bytes);
+        if input.end == 0 {
+            return None;
+        }
+    }
+
+    let byte = input.bytes[input.start];
+    input.start += 1;
+    Some(byte)
+}
+
+pub fn write(buffer: &[u8]) {
+    let mut stdout = io::stdout().lock();
+
+    stdout
+        .write_all(buffer)
+        .expect("Error writing to output stream");
+
+    stdout.flush().expect("Error flushing buffer");
+}
@@ -0,0 +1,189 @@
+//! The colors text and the status bar are drawn in.
+
+use std::env;
+
+/// A color as the config file names it: one of the 16 basic colors by name, like `red` or
+/// `bright-blue`, a number from the 256 color palette, or `#rrggbb`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Color {
+    /// Whatever the terminal uses when nothing is set.
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// How many colors the terminal can show. Colors it can't are drawn as the closest one
+/// it can.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    Basic,
+    Palette,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Guesses from the environment, the way terminals advertise it.
+    pub fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Palette
+        } else {
+            ColorDepth::Basic
+        }
+    }
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+impl Color {
+    pub fn parse(name: &str) -> Option<Color> {
+        let name = name.to_ascii_lowercase();
+        if name == "default" {
+            return Some(Color::Default);
+        }
+        if let Ok(index) = name.parse() {
+            return Some(Color::Indexed(index));
+        }
+        if let Some(hex) = name.strip_prefix('#').filter(|hex| hex.len() == 6) {
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+
+        let (name, bright) = match name.strip_prefix("bright-") {
+            Some(name) => (name, 8),
+            None => (name.as_str(), 0),
+        };
+        let index = COLOR_NAMES.iter().position(|&known| known == name)?;
+        Some(Color::Indexed(index as u8 + bright))
+    }
+
+    /// The SGR parameters that set this as the text color, or with `background` as the
+    /// color behind it, on a terminal with `depth` colors. The basic colors use their own
+    /// short codes.
+    pub fn sgr(self, background: bool, depth: ColorDepth) -> String {
+        let base = if background { 40 } else { 30 };
+        let color = match (self, depth) {
+            (Color::Rgb(..), ColorDepth::Palette) => Color::Indexed(nearest(self.rgb(), 16..=255)),
+            (Color::Rgb(..), ColorDepth::Basic) | (Color::Indexed(16..), ColorDepth::Basic) => {
+                Color::Indexed(nearest(self.rgb(), 0..=15))
+            }
+            _ => self,
+        };
+
+        match color {
+            Color::Default => (base + 9).to_string(),
+            Color::Indexed(index @ 0..=7) => (base + index as u32).to_string(),
+            Color::Indexed(index @ 8..=15) => (base + 60 + index as u32 - 8).to_string(),
+            Color::Indexed(index) => format!("{};5;{}", base + 8, index),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+
+    /// What the color looks like, taking the palette to be xterm's.
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Default => (0, 0, 0),
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(index @ 0..=15) => BASIC_RGB[index as usize],
+            // a 6x6x6 cube of colors, then 24 grays going from dark to light
+            Color::Indexed(index @ 16..=231) => {
+                let level = |i: u8| if i == 0 { 0 } else { 55 + i * 40 };
+                let i = index - 16;
+                (level(i / 36), level(i / 6 % 6), level(i % 6))
+            }
+            Color::Indexed(index) => {
+                let gray = 8 + (index - 232) * 10;
+                (gray, gray, gray)
+            }
+        }
+    }
+}
+
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The palette color out of `indices` that's closest to `rgb`.
+fn nearest(rgb: (u8, u8, u8), indices: std::ops::RangeInclusive<u8>) -> u8 {
+    let distance = |(r, g, b): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, rgb.0) + d(g, rgb.1) + d(b, rgb.2)
+    };
+    indices
+        .min_by_key(|&index| distance(Color::Indexed(index).rgb()))
+        .unwrap_or(0)
+}
+
+pub struct Theme {
+    /// The status bar's colors. With both left to the default it's drawn in reverse.
+    pub status_fg: Color,
+    pub status_bg: Color,
+    pub keyword: Color,
+    pub type_name: Color,
+    pub string: Color,
+    pub comment: Color,
+    pub number: Color,
+    /// The color behind selected text, which is drawn in reverse while it's the default.
+    pub selection: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            status_fg: Color::Default,
+            status_bg: Color::Default,
+            keyword: Color::Indexed(3),
+            type_name: Color::Indexed(2),
+            string: Color::Indexed(5),
+            comment: Color::Indexed(6),
+            number: Color::Indexed(1),
+            selection: Color::Default,
+        }
+    }
+}
+
+impl Theme {
+    /// The color set by `color_<name>` in the config file.
+    pub fn color_mut(&mut self, name: &str) -> Option<&mut Color> {
+        match name {
+            "status_fg" => Some(&mut self.status_fg),
+            "status_bg" => Some(&mut self.status_bg),
+            "keyword" => Some(&mut self.keyword),
+            "type" => Some(&mut self.type_name),
+            "string" => Some(&mut self.string),
+            "comment" => Some(&mut self.comment),
+            "number" => Some(&mut self.number),
+            "selection" => Some(&mut self.selection),
+            _ => None,
+        }
+    }
+
+    /// The SGR parameters the status bar is drawn with.
+    pub fn status_bar_sgr(&self, depth: ColorDepth) -> String {
+        match (self.status_fg, self.status_bg) {
+            (Color::Default, Color::Default) => String::from("7"),
+            (fg, bg) => format!("{};{}", fg.sgr(false, depth), bg.sgr(true, depth)),
+        }
+    }
+}